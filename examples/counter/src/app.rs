@@ -1,8 +1,7 @@
 use willow::{
-    cmd,
     events::on_click,
     html::{button, div, text, Html},
-    Cmd, Program,
+    Cmd, Program, Sub,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -20,12 +19,12 @@ fn init() -> Model {
     Model { counter: 4 }
 }
 
-fn update(msg: &Msg, model: &mut Model) -> Box<Cmd<Msg>> {
+fn update(msg: &Msg, model: &mut Model) -> Cmd<Msg> {
     match msg {
         Msg::Increment => model.counter += 1,
         Msg::Decrement => model.counter -= 1,
     }
-    Box::new(cmd::None)
+    Cmd::none()
 }
 
 fn view(model: &Model) -> Html<Msg> {
@@ -39,6 +38,10 @@ fn view(model: &Model) -> Html<Msg> {
     )
 }
 
+fn subscriptions(_: &Model) -> Sub<Msg> {
+    Sub::none()
+}
+
 pub fn main() -> Program<Model, Msg> {
-    Program::new(view, update, init())
+    Program::new(view, update, subscriptions, init())
 }
@@ -8,15 +8,16 @@ use willow::{
         autofocus, checked, class, class_list, for_, hidden, href, id, key, name, placeholder,
         style, type_, value,
     },
-    events::{on_blur, on_click, on_double_click, on_enter, on_input, on_input2},
+    events::{on_blur, on_click, on_double_click, on_enter, on_input, on_input_with},
     html::{
         a, button, div, footer, h1, header, input, label, li, p, section, span, strong, text, ul,
         Html,
     },
-    Cmd, Program,
+    storage::LoadError,
+    Cmd, Program, Sub,
 };
 
-use crate::storage::{self, SetStorage};
+use crate::storage;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Msg {
@@ -29,9 +30,10 @@ pub enum Msg {
     Delete(i32),
     ChangeVisibility(&'static str),
     DeleteCompleted,
+    Loaded(Result<Option<Model>, LoadError>),
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Model {
     entries: Vec<Entry>,
     field: String,
@@ -39,7 +41,7 @@ pub struct Model {
     visibility: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
     description: String,
     completed: bool,
@@ -48,7 +50,7 @@ pub struct Entry {
 }
 
 fn init() -> Model {
-    storage::get_model()
+    Model::default()
 
     // Model {
     //     entries,
@@ -58,8 +60,15 @@ fn init() -> Model {
     // }
 }
 
-fn update(msg: &Msg, model: &mut Model) -> Box<Cmd<Msg>> {
+fn update(msg: &Msg, model: &mut Model) -> Cmd<Msg> {
     match msg {
+        Msg::Loaded(Ok(loaded)) => {
+            if let Some(loaded) = loaded {
+                *model = loaded.clone();
+            }
+            return Cmd::none();
+        }
+        Msg::Loaded(Err(err)) => return storage::keep_unreadable(err),
         Msg::UpdateField(val) => model.field = val.to_owned(),
         Msg::Add => {
             if !model.field.is_empty() {
@@ -107,7 +116,7 @@ fn update(msg: &Msg, model: &mut Model) -> Box<Cmd<Msg>> {
         }
         Msg::DeleteCompleted => model.entries.retain(|entry| !entry.completed),
     };
-    Box::new(SetStorage(model.clone()))
+    storage::save(model)
 }
 
 fn view(model: &Model) -> Html<Msg> {
@@ -188,6 +197,7 @@ fn view_entries(visibility: &String, entries: &Vec<Entry>) -> Html<Msg> {
 }
 
 fn view_entry(todo: &Entry) -> Html<Msg> {
+    let todo_id = todo.id;
     li(
         &[
             key(todo.id.to_string()),
@@ -219,7 +229,7 @@ fn view_entry(todo: &Entry) -> Html<Msg> {
                     value(&todo.description),
                     name("title"),
                     id(&format!("todo-{}", todo.id.to_string())),
-                    on_input2(todo.id, |todo_id, val| Msg::UpdateEntry(todo_id, val)),
+                    on_input_with(todo_id, move |val| Msg::UpdateEntry(todo_id, val)),
                     on_blur(Msg::EditingEntry(todo.id, false)),
                     on_enter(Msg::EditingEntry(todo.id, false)),
                 ],
@@ -318,6 +328,109 @@ fn info_footer() -> Html<Msg> {
     )
 }
 
+fn subscriptions(_: &Model) -> Sub<Msg> {
+    Sub::none()
+}
+
 pub fn main() -> Program<Model, Msg> {
-    Program::new(view, update, init())
+    Program::new(view, update, subscriptions, init())
+        .with_init_cmd(storage::load())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use willow::{
+        decode,
+        html::EventToMessage,
+        storage::{set_store, Area, MemoryStore, Store},
+        testing::TestProgram,
+        Dispatcher,
+    };
+
+    use super::{main, update, view_entry, Entry, Model, Msg};
+    use crate::storage;
+
+    #[test]
+    fn view_entry_shows_a_completed_todo() {
+        let entry = Entry {
+            description: "Buy milk".to_owned(),
+            completed: true,
+            editing: false,
+            id: 3,
+        };
+        let html = view_entry(&entry);
+
+        assert_eq!(
+            html.find("li").unwrap().property("className"),
+            Some(&"completed".into())
+        );
+        assert_eq!(html.find("label").unwrap().text_content(), "Buy milk");
+        assert_eq!(
+            html.find("li > .view > .toggle")
+                .unwrap()
+                .property("checked"),
+            Some(&true.into())
+        );
+        assert_eq!(
+            html.find(".destroy").unwrap().events(),
+            vec![(
+                "click",
+                &EventToMessage::Decoder(decode::succeed(Msg::Delete(3)))
+            )]
+        );
+        assert_eq!(html.find_all("#todo-3.edit[name=title]").len(), 1);
+    }
+
+    #[test]
+    fn update_saves_the_model() {
+        let mut model = Model {
+            field: "Buy milk".to_owned(),
+            ..Default::default()
+        };
+        set_store(Area::Local, Rc::new(MemoryStore::new()));
+
+        update(&Msg::Add, &mut model)
+            .run(&Dispatcher::new(|_| ()))
+            .unwrap();
+
+        assert_eq!(model.entries.len(), 1);
+        assert_eq!(storage::model().get(), Ok(Some(model)));
+    }
+
+    #[test]
+    fn adds_a_todo_when_enter_is_pressed() {
+        let mut app = TestProgram::new(main());
+
+        app.input(".new-todo", "Buy milk").unwrap();
+        app.key_down(".new-todo", "Enter").unwrap();
+
+        assert_eq!(app.find_all(".todo-list li").len(), 1);
+        assert_eq!(
+            app.find(".todo-list label").unwrap().text_content(),
+            "Buy milk"
+        );
+        assert_eq!(
+            app.find(".new-todo").unwrap().property("value"),
+            Some(&"".into())
+        );
+    }
+
+    #[test]
+    fn keeps_saved_data_that_can_not_be_loaded() {
+        let store = Rc::new(MemoryStore::new());
+        set_store(Area::Local, store.clone());
+        store.set_item("todomvc::data", r#"{"todos":[]}"#).unwrap();
+
+        let mut app = TestProgram::new(main());
+        app.run_cmds().unwrap();
+        app.run_cmds().unwrap();
+
+        assert_eq!(app.model(), &Model::default());
+        assert_eq!(
+            store.get_item("todomvc::unreadable"),
+            Ok(Some(r#""{\"todos\":[]}""#.to_owned()))
+        );
+    }
 }
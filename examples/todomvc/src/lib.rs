@@ -1,5 +1,3 @@
-#![feature(arbitrary_self_types)]
-
 extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
@@ -29,7 +27,7 @@ cfg_if! {
 pub fn main() {
     utils::set_panic_hook();
     let program = Rc::new(app::main());
-    program.start();
+    program.start("app").expect("could not start the program");
     // mem::forget(program);
 
     // let model = app::init();
@@ -1,41 +1,32 @@
-use std::default::Default;
-
-use wasm_bindgen::JsValue;
-use web_sys;
-
-use willow::{console_log, Cmd};
+use willow::{
+    storage::{self, Area, LoadError, Versioned},
+    Cmd,
+};
 
 use crate::app::{Model, Msg};
 
-pub struct SetStorage(pub Model);
+const KEY: &str = "todomvc::data";
+// Where data that can not be read is kept, so that saving does not throw it away
+const UNREADABLE_KEY: &str = "todomvc::unreadable";
 
-impl Cmd<Msg> for SetStorage {
-    fn run(&self) -> Result<(), JsValue> {
-        let window = web_sys::window().expect("no global `window` exists");
-        let local_storage = window.local_storage();
-        if let Ok(Some(local_storage)) = local_storage {
-            let data = serde_json::to_string(&self.0)
-                .map_err(|err| JsValue::from_str(&err.to_string()))?;
-            if let Err(err) = local_storage.set_item("todomvc::data", &data) {
-                console_log!("Could not write to local storage, {:?}", err);
-            }
-        }
+/// The saved model. Add a migration here when `Model` changes in a way that old data can not
+/// be deserialized into.
+pub fn model() -> Versioned<Model> {
+    Versioned::new(Area::Local, KEY)
+}
 
-        Ok(())
-    }
+pub fn save(model: &Model) -> Cmd<Msg> {
+    self::model().save(model)
 }
 
-pub fn get_model() -> Model {
-    let window = web_sys::window().expect("no global `window` exists");
-    let local_storage = window.local_storage();
+pub fn load() -> Cmd<Msg> {
+    model().load(Msg::Loaded)
+}
 
-    if let Ok(Some(local_storage)) = local_storage {
-        if let Ok(Some(s)) = local_storage.get_item("todomvc::data") {
-            serde_json::from_str(&s).ok().unwrap_or_default()
-        } else {
-            Default::default()
-        }
-    } else {
-        Default::default()
+/// Keep the data that could not be loaded out of the way
+pub fn keep_unreadable(err: &LoadError) -> Cmd<Msg> {
+    match err {
+        LoadError::Unreadable { data, .. } => storage::save(Area::Local, UNREADABLE_KEY, data),
+        LoadError::Storage(_) => Cmd::none(),
     }
 }
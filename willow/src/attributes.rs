@@ -1,5 +1,34 @@
 use crate::html::{Attribute, PropertyValue};
 
+/// The DOM properties that are reflected as an attribute with another name
+const RENAMED: &[(&str, &str)] = &[
+    ("className", "class"),
+    ("htmlFor", "for"),
+    ("tabIndex", "tabindex"),
+    ("readOnly", "readonly"),
+    ("maxLength", "maxlength"),
+    ("colSpan", "colspan"),
+    ("rowSpan", "rowspan"),
+    ("contentEditable", "contenteditable"),
+];
+
+/// The attribute a DOM property is reflected as
+pub(crate) fn attribute_name(property: &str) -> &str {
+    RENAMED
+        .iter()
+        .find(|(renamed, _)| *renamed == property)
+        .map_or(property, |(_, attribute)| attribute)
+}
+
+/// The DOM property an attribute is reflected as
+#[cfg(feature = "web")]
+pub(crate) fn property_name(attribute: &str) -> &str {
+    RENAMED
+        .iter()
+        .find(|(_, renamed)| *renamed == attribute)
+        .map_or(attribute, |(property, _)| property)
+}
+
 macro_rules! string_property {
     ($x:ident, $tag:expr) => {
         pub fn $x<Msg>(value: &str) -> Attribute<Msg> {
@@ -0,0 +1,88 @@
+//! The operations the renderer needs from a DOM. `web::WebBackend` renders to the browser,
+//! `mock::MockBackend` to an in-memory tree that can be inspected natively.
+
+use std::rc::Rc;
+
+use crate::{
+    dom_event::Event,
+    html::{EventOptions, PropertyValue},
+    Error,
+};
+
+pub mod mock;
+#[cfg(feature = "web")]
+pub mod web;
+
+pub trait Backend {
+    type Node: Clone + PartialEq;
+    // What is needed to remove a listener again
+    type Listener: 'static;
+
+    fn create_element(&self, tag: &str) -> Result<Self::Node, Error>;
+    fn create_text(&self, text: &str) -> Result<Self::Node, Error>;
+    fn set_text(&self, node: &Self::Node, text: &str) -> Result<(), Error>;
+
+    fn set_property(
+        &self,
+        node: &Self::Node,
+        name: &str,
+        value: &PropertyValue,
+    ) -> Result<(), Error>;
+    fn remove_property(&self, node: &Self::Node, name: &str) -> Result<(), Error>;
+    fn set_style(&self, node: &Self::Node, property: &str, value: &str) -> Result<(), Error>;
+    fn remove_style(&self, node: &Self::Node, property: &str) -> Result<(), Error>;
+
+    fn add_listener(
+        &self,
+        node: &Self::Node,
+        type_: &str,
+        options: &EventOptions,
+        handler: Rc<Fn(Event)>,
+    ) -> Result<Self::Listener, Error>;
+    fn remove_listener(
+        &self,
+        node: &Self::Node,
+        type_: &str,
+        options: &EventOptions,
+        listener: Self::Listener,
+    ) -> Result<(), Error>;
+
+    fn child(&self, parent: &Self::Node, index: usize) -> Option<Self::Node>;
+    fn child_count(&self, parent: &Self::Node) -> usize;
+    /// Insert `child` before `reference`, or last if there is no reference. A child that is
+    /// already in `parent` is moved.
+    fn insert_before(
+        &self,
+        parent: &Self::Node,
+        child: &Self::Node,
+        reference: Option<&Self::Node>,
+    ) -> Result<(), Error>;
+    fn replace_child(
+        &self,
+        parent: &Self::Node,
+        new: &Self::Node,
+        old: &Self::Node,
+    ) -> Result<(), Error>;
+    fn remove_child(&self, parent: &Self::Node, child: &Self::Node) -> Result<(), Error>;
+
+    // Reading existing nodes, used when hydrating
+
+    /// The lower case tag name of an element, `None` for text nodes
+    fn tag_name(&self, node: &Self::Node) -> Option<String>;
+    /// The text of a text node, `None` for elements
+    fn text(&self, node: &Self::Node) -> Option<String>;
+    fn property(&self, node: &Self::Node, name: &str) -> Option<PropertyValue>;
+    fn style(&self, node: &Self::Node, property: &str) -> Option<String>;
+    /// The properties an element has from the attributes in its markup, by property name
+    fn property_names(&self, node: &Self::Node) -> Vec<String>;
+    fn style_names(&self, node: &Self::Node) -> Vec<String>;
+
+    // Finding the handlers for delegated events, see `delegate`
+
+    fn parent(&self, node: &Self::Node) -> Option<Self::Node>;
+    /// The node `event` was dispatched to
+    fn event_target(&self, event: &Event) -> Option<Self::Node>;
+
+    /// Where `node` is, for error messages
+    fn describe(&self, node: &Self::Node) -> String;
+}
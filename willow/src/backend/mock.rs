@@ -0,0 +1,614 @@
+//! An in-memory DOM that records every operation, to test rendering natively
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use super::Backend;
+use crate::{
+    dom_event::{self, Event, EventData},
+    html::{EventOptions, PropertyValue},
+    Error,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    CreateElement(NodeId, String),
+    CreateText(NodeId, String),
+    SetText(NodeId, String),
+    SetProperty(NodeId, String, PropertyValue),
+    RemoveProperty(NodeId, String),
+    SetStyle(NodeId, String, String),
+    RemoveStyle(NodeId, String),
+    AddListener(NodeId, String, EventOptions),
+    RemoveListener(NodeId, String),
+    InsertBefore {
+        parent: NodeId,
+        child: NodeId,
+        reference: Option<NodeId>,
+    },
+    ReplaceChild {
+        parent: NodeId,
+        new: NodeId,
+        old: NodeId,
+    },
+    RemoveChild {
+        parent: NodeId,
+        child: NodeId,
+    },
+}
+
+enum Content {
+    Element {
+        tag: String,
+        properties: Vec<(String, PropertyValue)>,
+        styles: Vec<(String, String)>,
+    },
+    Text(String),
+}
+
+type Handler = Rc<Fn(Event)>;
+
+struct MockListener {
+    id: usize,
+    type_: String,
+    options: EventOptions,
+    handler: Handler,
+}
+
+struct MockNode {
+    content: Content,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    listeners: Vec<MockListener>,
+}
+
+pub struct MockBackend {
+    nodes: RefCell<Vec<MockNode>>,
+    operations: RefCell<Vec<Operation>>,
+    next_listener: Cell<usize>,
+    // The node of the event being dispatched, see `event_target`
+    target: Cell<Option<NodeId>>,
+}
+
+impl MockBackend {
+    /// A new DOM with a single `div` as the root, see `root`
+    pub fn new() -> Self {
+        let backend = MockBackend {
+            nodes: RefCell::new(vec![]),
+            operations: RefCell::new(vec![]),
+            next_listener: Cell::new(0),
+            target: Cell::new(None),
+        };
+        backend.add_node(Content::Element {
+            tag: "div".to_owned(),
+            properties: vec![],
+            styles: vec![],
+        });
+        backend
+    }
+
+    /// The node to render into
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// The operations done since the backend was created, or since the last call to
+    /// `clear_operations`
+    pub fn operations(&self) -> Vec<Operation> {
+        self.operations.borrow().clone()
+    }
+
+    pub fn clear_operations(&self) {
+        self.operations.borrow_mut().clear();
+    }
+
+    pub fn children(&self, node: NodeId) -> Vec<NodeId> {
+        self.nodes.borrow()[node.0].children.clone()
+    }
+
+    /// The children of `node` as HTML, with properties written by their property name
+    pub fn inner_html(&self, node: NodeId) -> String {
+        self.children(node)
+            .into_iter()
+            .map(|child| self.outer_html(child))
+            .collect()
+    }
+
+    pub fn outer_html(&self, node: NodeId) -> String {
+        let nodes = self.nodes.borrow();
+        match &nodes[node.0].content {
+            Content::Text(text) => text.clone(),
+            Content::Element {
+                tag,
+                properties,
+                styles,
+            } => {
+                let mut html = format!("<{}", tag);
+                for (name, value) in properties {
+                    match value {
+                        PropertyValue::String(value) => {
+                            html.push_str(&format!(" {}=\"{}\"", name, value))
+                        }
+                        PropertyValue::Bool(true) => html.push_str(&format!(" {}", name)),
+                        PropertyValue::Bool(false) => {}
+                    }
+                }
+                if !styles.is_empty() {
+                    let styles = styles
+                        .iter()
+                        .map(|(property, value)| format!("{}: {}", property, value))
+                        .collect::<Vec<_>>();
+                    html.push_str(&format!(" style=\"{}\"", styles.join("; ")));
+                }
+                format!("{}>{}</{}>", html, self.inner_html(node), tag)
+            }
+        }
+    }
+
+    /// Dispatch an event to `node` like a browser would: to the capturing listeners of its
+    /// ancestors from the top, to the listeners of `node`, and then to the other listeners of
+    /// the ancestors if the event bubbles. Propagation stops after the node where a listener
+    /// called `stop_propagation`, and listeners added with `once` are removed as they are called.
+    pub fn dispatch_event(&self, node: NodeId, data: EventData) {
+        let mut ancestors = vec![];
+        let mut current = self.parent(&node);
+        while let Some(ancestor) = current {
+            ancestors.push(ancestor);
+            current = self.parent(&ancestor);
+        }
+
+        let mut path = ancestors
+            .iter()
+            .rev()
+            .map(|ancestor| (*ancestor, Some(true)))
+            .collect::<Vec<_>>();
+        path.push((node, None));
+        if dom_event::bubbles(&data.type_) {
+            path.extend(ancestors.iter().map(|ancestor| (*ancestor, Some(false))));
+        }
+
+        let event = Event::from_data(data);
+        self.target.set(Some(node));
+        for (current, capture) in path {
+            for handler in self.take_handlers(current, &event.type_(), capture) {
+                handler(event.clone());
+            }
+            if event.propagation_stopped() {
+                break;
+            }
+        }
+        self.target.set(None);
+    }
+
+    pub fn listener_count(&self, node: NodeId) -> usize {
+        self.nodes.borrow()[node.0].listeners.len()
+    }
+
+    fn add_node(&self, content: Content) -> NodeId {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(MockNode {
+            content,
+            parent: None,
+            children: vec![],
+            listeners: vec![],
+        });
+        NodeId(nodes.len() - 1)
+    }
+
+    // The handlers on `node` for events of `type_`, of the listeners that capture or not if
+    // `capture` is given. The listeners that only listen once are removed.
+    fn take_handlers(&self, node: NodeId, type_: &str, capture: Option<bool>) -> Vec<Handler> {
+        let listeners = &mut self.nodes.borrow_mut()[node.0].listeners;
+        let matches = |listener: &MockListener| {
+            listener.type_ == type_
+                && capture.is_none_or(|capture| listener.options.capture == capture)
+        };
+        let handlers = listeners
+            .iter()
+            .filter(|listener| matches(listener))
+            .map(|listener| listener.handler.clone())
+            .collect();
+        listeners.retain(|listener| !(matches(listener) && listener.options.once));
+        handlers
+    }
+
+    fn record(&self, operation: Operation) {
+        self.operations.borrow_mut().push(operation);
+    }
+
+    fn detach(&self, child: NodeId) {
+        let mut nodes = self.nodes.borrow_mut();
+        if let Some(parent) = nodes[child.0].parent.take() {
+            nodes[parent.0].children.retain(|node| *node != child);
+        }
+    }
+
+    fn with_element<T>(
+        &self,
+        node: NodeId,
+        f: impl FnOnce(&mut Vec<(String, PropertyValue)>, &mut Vec<(String, String)>) -> T,
+    ) -> Result<T, Error> {
+        match &mut self.nodes.borrow_mut()[node.0].content {
+            Content::Element {
+                properties, styles, ..
+            } => Ok(f(properties, styles)),
+            Content::Text(_) => Err(Error::from(format!("{:?} is not an element", node))),
+        }
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for MockBackend {
+    type Node = NodeId;
+    type Listener = usize;
+
+    fn create_element(&self, tag: &str) -> Result<NodeId, Error> {
+        let node = self.add_node(Content::Element {
+            tag: tag.to_owned(),
+            properties: vec![],
+            styles: vec![],
+        });
+        self.record(Operation::CreateElement(node, tag.to_owned()));
+        Ok(node)
+    }
+
+    fn create_text(&self, text: &str) -> Result<NodeId, Error> {
+        let node = self.add_node(Content::Text(text.to_owned()));
+        self.record(Operation::CreateText(node, text.to_owned()));
+        Ok(node)
+    }
+
+    fn set_text(&self, node: &NodeId, text: &str) -> Result<(), Error> {
+        match &mut self.nodes.borrow_mut()[node.0].content {
+            Content::Text(current) => *current = text.to_owned(),
+            Content::Element { .. } => {
+                return Err(Error::from(format!("{:?} is not a text node", node)));
+            }
+        }
+        self.record(Operation::SetText(*node, text.to_owned()));
+        Ok(())
+    }
+
+    fn set_property(&self, node: &NodeId, name: &str, value: &PropertyValue) -> Result<(), Error> {
+        self.with_element(*node, |properties, _| {
+            properties.retain(|(current, _)| current != name);
+            properties.push((name.to_owned(), value.clone()));
+        })?;
+        self.record(Operation::SetProperty(
+            *node,
+            name.to_owned(),
+            value.clone(),
+        ));
+        Ok(())
+    }
+
+    fn remove_property(&self, node: &NodeId, name: &str) -> Result<(), Error> {
+        self.with_element(*node, |properties, _| {
+            properties.retain(|(current, _)| current != name)
+        })?;
+        self.record(Operation::RemoveProperty(*node, name.to_owned()));
+        Ok(())
+    }
+
+    fn set_style(&self, node: &NodeId, property: &str, value: &str) -> Result<(), Error> {
+        self.with_element(*node, |_, styles| {
+            styles.retain(|(current, _)| current != property);
+            styles.push((property.to_owned(), value.to_owned()));
+        })?;
+        self.record(Operation::SetStyle(
+            *node,
+            property.to_owned(),
+            value.to_owned(),
+        ));
+        Ok(())
+    }
+
+    fn remove_style(&self, node: &NodeId, property: &str) -> Result<(), Error> {
+        self.with_element(*node, |_, styles| {
+            styles.retain(|(current, _)| current != property)
+        })?;
+        self.record(Operation::RemoveStyle(*node, property.to_owned()));
+        Ok(())
+    }
+
+    fn add_listener(
+        &self,
+        node: &NodeId,
+        type_: &str,
+        options: &EventOptions,
+        handler: Rc<Fn(Event)>,
+    ) -> Result<usize, Error> {
+        let id = self.next_listener.get();
+        self.next_listener.set(id + 1);
+        self.nodes.borrow_mut()[node.0]
+            .listeners
+            .push(MockListener {
+                id,
+                type_: type_.to_owned(),
+                options: *options,
+                handler,
+            });
+        self.record(Operation::AddListener(*node, type_.to_owned(), *options));
+        Ok(id)
+    }
+
+    fn remove_listener(
+        &self,
+        node: &NodeId,
+        type_: &str,
+        _: &EventOptions,
+        listener: usize,
+    ) -> Result<(), Error> {
+        self.nodes.borrow_mut()[node.0]
+            .listeners
+            .retain(|current| current.id != listener);
+        self.record(Operation::RemoveListener(*node, type_.to_owned()));
+        Ok(())
+    }
+
+    fn child(&self, parent: &NodeId, index: usize) -> Option<NodeId> {
+        self.nodes.borrow()[parent.0].children.get(index).cloned()
+    }
+
+    fn child_count(&self, parent: &NodeId) -> usize {
+        self.nodes.borrow()[parent.0].children.len()
+    }
+
+    fn insert_before(
+        &self,
+        parent: &NodeId,
+        child: &NodeId,
+        reference: Option<&NodeId>,
+    ) -> Result<(), Error> {
+        self.detach(*child);
+
+        let mut nodes = self.nodes.borrow_mut();
+        let index = match reference {
+            Some(reference) => nodes[parent.0]
+                .children
+                .iter()
+                .position(|node| node == reference)
+                .ok_or_else(|| Error::from(format!("{:?} is not in {:?}", reference, parent)))?,
+            None => nodes[parent.0].children.len(),
+        };
+        nodes[parent.0].children.insert(index, *child);
+        nodes[child.0].parent = Some(*parent);
+        drop(nodes);
+
+        self.record(Operation::InsertBefore {
+            parent: *parent,
+            child: *child,
+            reference: reference.cloned(),
+        });
+        Ok(())
+    }
+
+    fn replace_child(&self, parent: &NodeId, new: &NodeId, old: &NodeId) -> Result<(), Error> {
+        self.detach(*new);
+
+        let mut nodes = self.nodes.borrow_mut();
+        let index = nodes[parent.0]
+            .children
+            .iter()
+            .position(|node| node == old)
+            .ok_or_else(|| Error::from(format!("{:?} is not in {:?}", old, parent)))?;
+        nodes[parent.0].children[index] = *new;
+        nodes[new.0].parent = Some(*parent);
+        nodes[old.0].parent = None;
+        drop(nodes);
+
+        self.record(Operation::ReplaceChild {
+            parent: *parent,
+            new: *new,
+            old: *old,
+        });
+        Ok(())
+    }
+
+    fn remove_child(&self, parent: &NodeId, child: &NodeId) -> Result<(), Error> {
+        if self.nodes.borrow()[child.0].parent != Some(*parent) {
+            return Err(Error::from(format!("{:?} is not in {:?}", child, parent)));
+        }
+        self.detach(*child);
+        self.record(Operation::RemoveChild {
+            parent: *parent,
+            child: *child,
+        });
+        Ok(())
+    }
+
+    fn tag_name(&self, node: &NodeId) -> Option<String> {
+        match &self.nodes.borrow()[node.0].content {
+            Content::Element { tag, .. } => Some(tag.clone()),
+            Content::Text(_) => None,
+        }
+    }
+
+    fn text(&self, node: &NodeId) -> Option<String> {
+        match &self.nodes.borrow()[node.0].content {
+            Content::Text(text) => Some(text.clone()),
+            Content::Element { .. } => None,
+        }
+    }
+
+    fn property(&self, node: &NodeId, name: &str) -> Option<PropertyValue> {
+        match &self.nodes.borrow()[node.0].content {
+            Content::Element { properties, .. } => properties
+                .iter()
+                .find(|(current, _)| current == name)
+                .map(|(_, value)| value.clone()),
+            Content::Text(_) => None,
+        }
+    }
+
+    fn style(&self, node: &NodeId, property: &str) -> Option<String> {
+        match &self.nodes.borrow()[node.0].content {
+            Content::Element { styles, .. } => styles
+                .iter()
+                .find(|(current, _)| current == property)
+                .map(|(_, value)| value.clone()),
+            Content::Text(_) => None,
+        }
+    }
+
+    fn property_names(&self, node: &NodeId) -> Vec<String> {
+        match &self.nodes.borrow()[node.0].content {
+            Content::Element { properties, .. } => {
+                properties.iter().map(|(name, _)| name.clone()).collect()
+            }
+            Content::Text(_) => vec![],
+        }
+    }
+
+    fn style_names(&self, node: &NodeId) -> Vec<String> {
+        match &self.nodes.borrow()[node.0].content {
+            Content::Element { styles, .. } => styles
+                .iter()
+                .map(|(property, _)| property.clone())
+                .collect(),
+            Content::Text(_) => vec![],
+        }
+    }
+
+    fn parent(&self, node: &NodeId) -> Option<NodeId> {
+        self.nodes.borrow()[node.0].parent
+    }
+
+    fn event_target(&self, _: &Event) -> Option<NodeId> {
+        self.target.get()
+    }
+
+    fn describe(&self, node: &NodeId) -> String {
+        let nodes = self.nodes.borrow();
+        let mut result = vec![format!("{:?}", node)];
+        let mut node = *node;
+        while let Some(parent) = nodes[node.0].parent {
+            result.push(format!("{:?}", parent));
+            node = parent;
+        }
+        result.join(" -> ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{MockBackend, NodeId, Operation};
+    use crate::backend::Backend;
+    use crate::dom_event::{Event, EventData};
+    use crate::html::EventOptions;
+
+    // Listen for clicks on `node`, logging `name` when they come
+    fn listen(
+        backend: &MockBackend,
+        log: &Rc<RefCell<Vec<&'static str>>>,
+        node: NodeId,
+        name: &'static str,
+        options: EventOptions,
+    ) {
+        let log = log.clone();
+        let handler = Rc::new(move |event: Event| {
+            log.borrow_mut().push(name);
+            if options.stop_propagation {
+                event.stop_propagation();
+            }
+        });
+        backend
+            .add_listener(&node, "click", &options, handler)
+            .unwrap();
+    }
+
+    fn click(backend: &MockBackend, node: NodeId) {
+        backend.dispatch_event(
+            node,
+            EventData {
+                type_: "click".to_owned(),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn records_the_options_of_listeners() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let options = EventOptions {
+            passive: true,
+            ..Default::default()
+        };
+
+        listen(
+            &backend,
+            &Rc::new(RefCell::new(vec![])),
+            root,
+            "root",
+            options,
+        );
+
+        assert_eq!(
+            backend.operations(),
+            vec![Operation::AddListener(root, "click".to_owned(), options)]
+        );
+    }
+
+    #[test]
+    fn dispatches_through_capture_target_and_bubbling_until_stopped() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let (parent, child) = (
+            backend.create_element("ul").unwrap(),
+            backend.create_element("li").unwrap(),
+        );
+        backend.insert_before(&root, &parent, None).unwrap();
+        backend.insert_before(&parent, &child, None).unwrap();
+        let log = Rc::new(RefCell::new(vec![]));
+        let capture = EventOptions {
+            capture: true,
+            ..Default::default()
+        };
+        let stop = EventOptions {
+            stop_propagation: true,
+            ..Default::default()
+        };
+
+        listen(&backend, &log, root, "root", Default::default());
+        listen(&backend, &log, parent, "parent", stop);
+        listen(&backend, &log, root, "root capture", capture);
+        listen(&backend, &log, child, "child", Default::default());
+
+        click(&backend, child);
+        assert_eq!(*log.borrow(), vec!["root capture", "child", "parent"]);
+        log.borrow_mut().clear();
+        click(&backend, root);
+        assert_eq!(*log.borrow(), vec!["root", "root capture"]);
+    }
+
+    #[test]
+    fn listeners_added_with_once_are_called_once() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let log = Rc::new(RefCell::new(vec![]));
+        let once = EventOptions {
+            once: true,
+            ..Default::default()
+        };
+
+        listen(&backend, &log, root, "once", once);
+        listen(&backend, &log, root, "always", Default::default());
+
+        click(&backend, root);
+        click(&backend, root);
+        assert_eq!(*log.borrow(), vec!["once", "always", "always"]);
+        assert_eq!(backend.listener_count(root), 1);
+    }
+}
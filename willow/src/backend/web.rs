@@ -0,0 +1,230 @@
+use std::rc::Rc;
+
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{self, AddEventListenerOptions, Document, HtmlElement, Node};
+
+use super::Backend;
+use crate::{
+    attributes::{attribute_name, property_name},
+    dom_event::Event,
+    html::{EventOptions, PropertyValue},
+    Error,
+};
+
+/// Renders to the browser DOM through `web_sys`
+pub struct WebBackend {
+    document: Document,
+}
+
+impl WebBackend {
+    pub fn new() -> Self {
+        let window = web_sys::window().expect("no global `window` exists");
+        let document = window.document().expect("should have a document on window");
+        WebBackend { document }
+    }
+}
+
+impl Default for WebBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn as_element(node: &Node) -> Result<&HtmlElement, Error> {
+    node.dyn_ref::<HtmlElement>()
+        .ok_or_else(|| Error::from(format!("{} is not an element", node.node_name())))
+}
+
+fn property_value_to_json_value(val: &PropertyValue) -> JsValue {
+    match val {
+        PropertyValue::String(ref value) => JsValue::from_str(value),
+        PropertyValue::Bool(value) => JsValue::from_bool(*value),
+    }
+}
+
+impl Backend for WebBackend {
+    type Node = Node;
+    type Listener = Closure<Fn(web_sys::Event)>;
+
+    fn create_element(&self, tag: &str) -> Result<Node, Error> {
+        Ok(self.document.create_element(tag)?.into())
+    }
+
+    fn create_text(&self, text: &str) -> Result<Node, Error> {
+        Ok(self.document.create_text_node(text).into())
+    }
+
+    fn set_text(&self, node: &Node, text: &str) -> Result<(), Error> {
+        node.set_text_content(Some(text));
+        Ok(())
+    }
+
+    fn set_property(&self, node: &Node, name: &str, value: &PropertyValue) -> Result<(), Error> {
+        Reflect::set(
+            node.as_ref(),
+            &JsValue::from_str(name),
+            &property_value_to_json_value(value),
+        )?;
+        Ok(())
+    }
+
+    fn remove_property(&self, node: &Node, name: &str) -> Result<(), Error> {
+        // TODO: I think I know why elm normalizes before adding and removing attributes. We should probably do the same
+        Reflect::delete_property(node.as_ref(), &JsValue::from_str(name))?;
+        // Properties from the markup are attributes, which are not removed with the property
+        if let Some(element) = node.dyn_ref::<web_sys::Element>() {
+            element.remove_attribute(attribute_name(name))?;
+        }
+        Ok(())
+    }
+
+    fn set_style(&self, node: &Node, property: &str, value: &str) -> Result<(), Error> {
+        as_element(node)?.style().set_property(property, value)?;
+        Ok(())
+    }
+
+    fn remove_style(&self, node: &Node, property: &str) -> Result<(), Error> {
+        as_element(node)?.style().remove_property(property)?;
+        Ok(())
+    }
+
+    fn add_listener(
+        &self,
+        node: &Node,
+        type_: &str,
+        options: &EventOptions,
+        handler: Rc<Fn(Event)>,
+    ) -> Result<Self::Listener, Error> {
+        let closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            handler(Event::from(event));
+        }) as Box<Fn(_)>);
+
+        let listener_options = AddEventListenerOptions::new();
+        listener_options.set_capture(options.capture);
+        listener_options.set_passive(options.passive);
+        listener_options.set_once(options.once);
+        (node.as_ref() as &web_sys::EventTarget)
+            .add_event_listener_with_callback_and_add_event_listener_options(
+                type_,
+                closure.as_ref().unchecked_ref(),
+                &listener_options,
+            )?;
+
+        Ok(closure)
+    }
+
+    fn remove_listener(
+        &self,
+        node: &Node,
+        type_: &str,
+        options: &EventOptions,
+        listener: Self::Listener,
+    ) -> Result<(), Error> {
+        // Only the capture flag tells listeners for the same function apart
+        (node.as_ref() as &web_sys::EventTarget).remove_event_listener_with_callback_and_bool(
+            type_,
+            listener.as_ref().unchecked_ref(),
+            options.capture,
+        )?;
+        Ok(())
+    }
+
+    fn child(&self, parent: &Node, index: usize) -> Option<Node> {
+        parent.child_nodes().item(index as u32)
+    }
+
+    fn child_count(&self, parent: &Node) -> usize {
+        parent.child_nodes().length() as usize
+    }
+
+    fn insert_before(
+        &self,
+        parent: &Node,
+        child: &Node,
+        reference: Option<&Node>,
+    ) -> Result<(), Error> {
+        parent.insert_before(child, reference)?;
+        Ok(())
+    }
+
+    fn replace_child(&self, parent: &Node, new: &Node, old: &Node) -> Result<(), Error> {
+        parent.replace_child(new, old)?;
+        Ok(())
+    }
+
+    fn remove_child(&self, parent: &Node, child: &Node) -> Result<(), Error> {
+        parent.remove_child(child)?;
+        Ok(())
+    }
+
+    fn tag_name(&self, node: &Node) -> Option<String> {
+        node.dyn_ref::<web_sys::Element>()
+            .map(|element| element.tag_name().to_lowercase())
+    }
+
+    fn text(&self, node: &Node) -> Option<String> {
+        if node.node_type() == Node::TEXT_NODE {
+            Some(node.text_content().unwrap_or_default())
+        } else {
+            None
+        }
+    }
+
+    fn property(&self, node: &Node, name: &str) -> Option<PropertyValue> {
+        let value = Reflect::get(node.as_ref(), &JsValue::from_str(name)).ok()?;
+        if let Some(value) = value.as_string() {
+            Some(PropertyValue::String(value))
+        } else {
+            value.as_bool().map(PropertyValue::Bool)
+        }
+    }
+
+    fn style(&self, node: &Node, property: &str) -> Option<String> {
+        as_element(node)
+            .ok()
+            .and_then(|element| element.style().get_property_value(property).ok())
+    }
+
+    fn property_names(&self, node: &Node) -> Vec<String> {
+        node.dyn_ref::<web_sys::Element>()
+            .map(|element| {
+                element
+                    .get_attribute_names()
+                    .iter()
+                    .filter_map(|name| name.as_string())
+                    .filter(|name| name != "style")
+                    .map(|name| property_name(&name).to_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn style_names(&self, node: &Node) -> Vec<String> {
+        as_element(node)
+            .map(|element| {
+                let style = element.style();
+                (0..style.length()).map(|index| style.item(index)).collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn parent(&self, node: &Node) -> Option<Node> {
+        node.parent_node()
+    }
+
+    fn event_target(&self, event: &Event) -> Option<Node> {
+        event.as_web()?.target()?.dyn_into::<Node>().ok()
+    }
+
+    fn describe(&self, node: &Node) -> String {
+        let mut result = vec![node.node_name()];
+        let mut node = node.to_owned();
+        while let Some(new_node) = node.parent_node() {
+            result.push(new_node.node_name());
+            node = new_node;
+        }
+        result.join(" -> ")
+    }
+}
@@ -1,20 +1,400 @@
-use wasm_bindgen::JsValue;
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr::fn_addr_eq;
+use std::rc::Rc;
 
-pub trait Cmd<Msg> {
-    fn run(&self) -> Result<(), JsValue>;
+use crate::{error::Error, html::MsgMapper, task};
 
-    fn boxed(self) -> Box<Self>
+/// Sends messages back into the running program. Every command gets one when it is run, and
+/// may keep a clone around if the result arrives later (timers, callbacks, ...).
+pub struct Dispatcher<Msg> {
+    dispatch: Rc<Fn(Msg)>,
+    immediate: Option<Rc<Fn(Msg)>>,
+}
+
+impl<Msg> Dispatcher<Msg> {
+    pub fn new<F>(dispatch: F) -> Self
+    where
+        F: Fn(Msg) + 'static,
+    {
+        Dispatcher {
+            dispatch: Rc::new(dispatch),
+            immediate: Option::None,
+        }
+    }
+
+    /// Use `immediate` for `dispatch_immediately`
+    pub fn with_immediate<F>(self, immediate: F) -> Self
+    where
+        F: Fn(Msg) + 'static,
+    {
+        Dispatcher {
+            dispatch: self.dispatch,
+            immediate: Some(Rc::new(immediate)),
+        }
+    }
+
+    /// A dispatcher for messages that are converted with `f` before they are sent here
+    pub fn map<A, F>(&self, f: F) -> Dispatcher<A>
+    where
+        Msg: 'static,
+        F: Fn(A) -> Msg + 'static,
+    {
+        let f = Rc::new(f);
+        let dispatch = self.dispatch.clone();
+        let immediate = self.immediate.clone();
+        let immediate_f = f.clone();
+        Dispatcher {
+            dispatch: Rc::new(move |message| dispatch(f(message))),
+            immediate: immediate.map(|immediate| {
+                Rc::new(move |message| immediate(immediate_f(message))) as Rc<Fn(A)>
+            }),
+        }
+    }
+
+    pub fn dispatch(&self, message: Msg) {
+        (self.dispatch)(message)
+    }
+
+    /// Dispatch and render right away, see `Program::dispatch_immediately`. Dispatchers without
+    /// a way to do that dispatch as usual.
+    pub fn dispatch_immediately(&self, message: Msg) {
+        match &self.immediate {
+            Some(immediate) => immediate(message),
+            Option::None => (self.dispatch)(message),
+        }
+    }
+}
+
+impl<Msg> Clone for Dispatcher<Msg> {
+    fn clone(&self) -> Self {
+        Dispatcher {
+            dispatch: self.dispatch.clone(),
+            immediate: self.immediate.clone(),
+        }
+    }
+}
+
+/// An effect for the runtime to run after `update`. Commands are plain values, so tests can
+/// compare what `update` returned instead of running it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cmd<Msg> {
+    None,
+    /// Send a message back into the program
+    Message(Msg),
+    /// Run every command, in order. A command that fails does not keep the ones after it from
+    /// running, and the first error is what the batch returns.
+    Batch(Vec<Cmd<Msg>>),
+    /// An `Effect` defined outside of willow, see `Cmd::effect`
+    Custom(Custom<Msg>),
+}
+
+impl<Msg: Clone + 'static> Cmd<Msg> {
+    pub fn none() -> Self {
+        Cmd::None
+    }
+
+    pub fn batch(cmds: Vec<Cmd<Msg>>) -> Self {
+        Cmd::Batch(cmds)
+    }
+
+    pub fn effect<E>(effect: E) -> Self
+    where
+        E: Effect<Msg> + PartialEq + Debug + 'static,
+    {
+        Cmd::Custom(Custom(Rc::new(effect)))
+    }
+
+    /// Run `future` in the background, and dispatch the message `to_message` gives for its
+    /// output. See `task` for chaining futures that can fail.
+    pub fn perform<F, T, M>(future: F, to_message: M) -> Self
+    where
+        F: Future<Output = T> + 'static,
+        M: FnOnce(T) -> Msg + 'static,
+    {
+        let future = async move { to_message(future.await) };
+        Cmd::effect(Perform(Rc::new(RefCell::new(Some(Box::pin(future))))))
+    }
+
+    /// Convert the messages of this command with `f`. This is used to run the commands of a
+    /// module with its own message type.
+    pub fn map<B, F>(self, f: F) -> Cmd<B>
+    where
+        B: Clone + 'static,
+        F: Fn(Msg) -> B + 'static,
+    {
+        self.map_with(&MsgMapper::new(f))
+    }
+
+    fn map_with<B: Clone + 'static>(self, mapper: &MsgMapper<Msg, B>) -> Cmd<B> {
+        match self {
+            Cmd::None => Cmd::None,
+            Cmd::Message(message) => Cmd::Message(mapper.call(message)),
+            Cmd::Batch(cmds) => {
+                Cmd::Batch(cmds.into_iter().map(|cmd| cmd.map_with(mapper)).collect())
+            }
+            Cmd::Custom(custom) => Cmd::effect(Mapped {
+                custom,
+                mapper: mapper.clone(),
+            }),
+        }
+    }
+
+    /// The effect of a custom command, if it is an `E`
+    pub fn downcast_ref<E: 'static>(&self) -> Option<&E> {
+        match self {
+            Cmd::Custom(custom) => custom.0.as_any().downcast_ref(),
+            _ => Option::None,
+        }
+    }
+
+    pub fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error> {
+        match self {
+            Cmd::None => Ok(()),
+            Cmd::Message(message) => {
+                dispatcher.dispatch(message.clone());
+                Ok(())
+            }
+            Cmd::Batch(cmds) => {
+                // Run every command even if one fails, and report the first error
+                let mut result = Ok(());
+                for cmd in cmds {
+                    let cmd_result = cmd.run(dispatcher);
+                    if result.is_ok() {
+                        result = cmd_result;
+                    }
+                }
+                result
+            }
+            Cmd::Custom(custom) => custom.0.run(dispatcher),
+        }
+    }
+}
+
+/// A side effect, like writing to local storage. Wrap it in a command with `Cmd::effect`.
+pub trait Effect<Msg> {
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error>;
+
+    /// Turn errors from this effect into a message instead of logging them
+    fn on_error(self, to_message: fn(Error) -> Msg) -> OnError<Self, Msg>
     where
         Self: Sized,
     {
-        Box::new(self)
+        OnError {
+            effect: self,
+            to_message,
+        }
+    }
+}
+
+/// A custom effect. Two are equal if they are the same type and compare equal.
+#[derive(Clone)]
+pub struct Custom<Msg>(Rc<AnyEffect<Msg>>);
+
+// What `Custom` needs from an effect, implemented for every effect that can be compared
+trait AnyEffect<Msg> {
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error>;
+    fn as_any(&self) -> &Any;
+    fn eq_any(&self, other: &Any) -> bool;
+    fn fmt_debug(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+impl<Msg, E> AnyEffect<Msg> for E
+where
+    E: Effect<Msg> + PartialEq + Debug + 'static,
+{
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error> {
+        Effect::run(self, dispatcher)
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn eq_any(&self, other: &Any) -> bool {
+        other.downcast_ref::<E>().is_some_and(|other| self == other)
+    }
+
+    fn fmt_debug(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt(f)
+    }
+}
+
+impl<Msg> PartialEq for Custom<Msg> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_any(other.0.as_any())
+    }
+}
+
+impl<Msg> Debug for Custom<Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_debug(f)
+    }
+}
+
+/// A future to run, see `Cmd::perform`. A future can only be run once, and is only equal to
+/// itself.
+pub struct Perform<Msg>(Rc<RefCell<Option<LocalFuture<Msg>>>>);
+
+type LocalFuture<Msg> = Pin<Box<Future<Output = Msg>>>;
+
+impl<Msg: 'static> Effect<Msg> for Perform<Msg> {
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error> {
+        let future = self
+            .0
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| Error::from("The future of this command has already been run"))?;
+        let dispatcher = dispatcher.clone();
+        task::spawn_local(async move { dispatcher.dispatch(future.await) });
+        Ok(())
+    }
+}
+
+impl<Msg> PartialEq for Perform<Msg> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<Msg> Debug for Perform<Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Perform")
+    }
+}
+
+/// A custom effect of another message type, see `Cmd::map`
+pub struct Mapped<A, B> {
+    custom: Custom<A>,
+    mapper: MsgMapper<A, B>,
+}
+
+impl<A: 'static, B: 'static> Effect<B> for Mapped<A, B> {
+    fn run(&self, dispatcher: &Dispatcher<B>) -> Result<(), Error> {
+        let mapper = self.mapper.clone();
+        self.custom
+            .0
+            .run(&dispatcher.map(move |message| mapper.call(message)))
+    }
+}
+
+impl<A, B> PartialEq for Mapped<A, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.custom == other.custom && self.mapper == other.mapper
     }
 }
 
-pub struct None;
+impl<A, B> Debug for Mapped<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Mapped").field(&self.custom).finish()
+    }
+}
 
-impl<Msg> Cmd<Msg> for None {
-    fn run(&self) -> Result<(), JsValue> {
+pub struct OnError<E, Msg> {
+    effect: E,
+    to_message: fn(Error) -> Msg,
+}
+
+impl<E, Msg> Effect<Msg> for OnError<E, Msg>
+where
+    E: Effect<Msg>,
+{
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error> {
+        if let Err(err) = self.effect.run(dispatcher) {
+            dispatcher.dispatch((self.to_message)(err));
+        }
         Ok(())
     }
 }
+
+impl<E: PartialEq, Msg> PartialEq for OnError<E, Msg> {
+    fn eq(&self, other: &Self) -> bool {
+        self.effect == other.effect && fn_addr_eq(self.to_message, other.to_message)
+    }
+}
+
+impl<E: Debug, Msg> Debug for OnError<E, Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OnError")
+            .field("effect", &self.effect)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cmd, Dispatcher, Effect};
+    use crate::Error;
+
+    #[derive(Debug, PartialEq)]
+    struct Save(i32);
+
+    impl<Msg> Effect<Msg> for Save {
+        fn run(&self, _: &Dispatcher<Msg>) -> Result<(), Error> {
+            Err(Error::from(format!("could not save {}", self.0)))
+        }
+    }
+
+    #[test]
+    fn commands_can_be_compared_and_inspected() {
+        assert_eq!(Cmd::effect(Save(1)), Cmd::<String>::effect(Save(1)));
+        assert_ne!(Cmd::effect(Save(1)), Cmd::<String>::effect(Save(2)));
+        assert_ne!(Cmd::effect(Save(1)), Cmd::Message("saved".to_owned()));
+        assert_eq!(
+            Cmd::<String>::effect(Save(3)).downcast_ref::<Save>(),
+            Some(&Save(3))
+        );
+        assert_eq!(Cmd::<String>::None.downcast_ref::<Save>(), None);
+        assert_eq!(
+            format!("{:?}", Cmd::<String>::effect(Save(4))),
+            "Custom(Save(4))"
+        );
+    }
+
+    #[test]
+    fn runs_effects() {
+        let messages = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let sent = messages.clone();
+        let dispatcher = Dispatcher::new(move |message| sent.borrow_mut().push(message));
+
+        Cmd::Message("hello".to_owned()).run(&dispatcher).unwrap();
+        assert!(Cmd::effect(Save(5)).run(&dispatcher).is_err());
+        Cmd::effect(Save(6).on_error(|err| err.to_string()))
+            .run(&dispatcher)
+            .unwrap();
+
+        assert_eq!(*messages.borrow(), vec!["hello", "could not save 6"]);
+    }
+
+    #[test]
+    fn batches_and_maps_commands() {
+        let messages = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let sent = messages.clone();
+        let dispatcher = Dispatcher::new(move |message| sent.borrow_mut().push(message));
+
+        let cmd = Cmd::batch(vec![
+            Cmd::Message(1),
+            Cmd::none(),
+            Cmd::effect(Save(2).on_error(|_| 2)),
+        ])
+        .map(|number| format!("number {}", number));
+
+        fn to_string(number: i32) -> String {
+            number.to_string()
+        }
+        assert_eq!(
+            Cmd::batch(vec![Cmd::Message(1), Cmd::effect(Save(2))]).map(to_string),
+            Cmd::batch(vec![Cmd::Message(1), Cmd::effect(Save(2))]).map(to_string)
+        );
+        assert_ne!(
+            Cmd::effect(Save(2)).map(to_string),
+            Cmd::effect(Save(3)).map(to_string)
+        );
+
+        cmd.run(&dispatcher).unwrap();
+        assert_eq!(*messages.borrow(), vec!["number 1", "number 2"]);
+    }
+}
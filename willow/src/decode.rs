@@ -0,0 +1,576 @@
+//! Read the message for an event from the event, like `Json.Decode` in Elm.
+//!
+//! A `Decoder` starts from one of the fields of the event, like `key` or `client_x`, and is
+//! built up with `map`, `and_then`, `filter` and friends. A decoder that fails gives no message,
+//! so `key().filter(is_enter)` only gives messages for the enter key. Use it with `events::on`.
+//!
+//! Decoders take `fn` pointers, not closures, so that two decoders can be compared. That is
+//! what keeps listeners from being registered again on every render. `map_keyed` takes a closure
+//! together with a key that is compared instead.
+
+use std::any::Any;
+use std::fmt::{self, Debug};
+use std::ptr::fn_addr_eq;
+use std::rc::Rc;
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    dom_event::Event,
+    html::{MsgMapper, RcEventClosure},
+};
+
+pub struct Decoder<T>(Rc<AnyDecode<T>>);
+
+// A step of a decoder
+trait Decode<T> {
+    fn decode(&self, event: &Event) -> Result<T, String>;
+
+    // Whether the state of the target element is read
+    fn reads_target(&self) -> bool;
+}
+
+// What `Decoder` needs from a step, implemented for every step that can be compared
+trait AnyDecode<T> {
+    fn decode(&self, event: &Event) -> Result<T, String>;
+    fn reads_target(&self) -> bool;
+    fn as_any(&self) -> &Any;
+    fn eq_any(&self, other: &Any) -> bool;
+    fn fmt_debug(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+impl<T, D> AnyDecode<T> for D
+where
+    D: Decode<T> + PartialEq + Debug + 'static,
+{
+    fn decode(&self, event: &Event) -> Result<T, String> {
+        Decode::decode(self, event)
+    }
+
+    fn reads_target(&self) -> bool {
+        Decode::reads_target(self)
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn eq_any(&self, other: &Any) -> bool {
+        other.downcast_ref::<D>().is_some_and(|other| self == other)
+    }
+
+    fn fmt_debug(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt(f)
+    }
+}
+
+impl<T: 'static> Decoder<T> {
+    fn new<D>(decode: D) -> Self
+    where
+        D: Decode<T> + PartialEq + Debug + 'static,
+    {
+        Decoder(Rc::new(decode))
+    }
+
+    /// A decoder that reads the event with `read`. `name` is only used for debugging.
+    pub fn from_fn(name: &'static str, read: fn(&Event) -> Result<T, String>) -> Self {
+        Decoder::new(Field {
+            name,
+            read,
+            reads_target: false,
+            custom: true,
+        })
+    }
+
+    pub fn map<B: 'static>(self, f: fn(T) -> B) -> Decoder<B> {
+        Decoder::new(Map { inner: self, f })
+    }
+
+    /// Give `value` when this decoder succeeds
+    pub fn map_to<B>(self, value: B) -> Decoder<B>
+    where
+        B: PartialEq + Debug + Clone + 'static,
+    {
+        map2(self, succeed(value), |_, value| value)
+    }
+
+    /// Like `map`, but with a closure. Decoders made by the same closure expression are equal
+    /// when their keys are, so the key must hold everything the closure captures that matters.
+    pub fn map_keyed<B, K, F>(self, key: K, f: F) -> Decoder<B>
+    where
+        B: 'static,
+        K: PartialEq + Debug + 'static,
+        F: Fn(T) -> B + 'static,
+    {
+        Decoder::new(MapKeyed {
+            inner: self,
+            f: RcEventClosure::keyed(key, f),
+        })
+    }
+
+    /// Decode the event again with the decoder `f` gives for the value of this one
+    pub fn and_then<B: 'static>(self, f: fn(T) -> Decoder<B>) -> Decoder<B> {
+        Decoder::new(AndThen { inner: self, f })
+    }
+
+    /// Fail if `predicate` does not hold for the value
+    pub fn filter(self, predicate: fn(&T) -> bool) -> Decoder<T> {
+        Decoder::new(Filter {
+            inner: self,
+            predicate,
+        })
+    }
+
+    pub(crate) fn map_with<B: 'static>(self, mapper: &MsgMapper<T, B>) -> Decoder<B> {
+        Decoder::new(MapWith {
+            inner: self,
+            mapper: mapper.clone(),
+        })
+    }
+}
+
+impl<T> Decoder<T> {
+    pub fn decode(&self, event: &Event) -> Result<T, String> {
+        self.0.decode(event)
+    }
+
+    /// Whether the decoder reads the state of the target element, like `target_value`. The
+    /// view has to be rendered right away after such events, or the element gets out of sync.
+    pub fn reads_target(&self) -> bool {
+        self.0.reads_target()
+    }
+}
+
+impl<T> Clone for Decoder<T> {
+    fn clone(&self) -> Self {
+        Decoder(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for Decoder<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || self.0.eq_any(other.0.as_any())
+    }
+}
+
+impl<T> Debug for Decoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_debug(f)
+    }
+}
+
+/// Always give `value`
+pub fn succeed<T: PartialEq + Debug + Clone + 'static>(value: T) -> Decoder<T> {
+    Decoder::new(Succeed(value))
+}
+
+/// Always fail with `message`
+pub fn fail<T: 'static>(message: &str) -> Decoder<T> {
+    Decoder::new(Fail(message.to_owned()))
+}
+
+/// Combine the values of two decoders with `f`
+pub fn map2<A, B, C>(a: Decoder<A>, b: Decoder<B>, f: fn(A, B) -> C) -> Decoder<C>
+where
+    A: 'static,
+    B: 'static,
+    C: 'static,
+{
+    Decoder::new(Map2 { a, b, f })
+}
+
+// The fields below are told apart by their name alone, as the same closure may have several
+// addresses or share one with another
+fn field<T: 'static>(name: &'static str, read: fn(&Event) -> Result<T, String>) -> Decoder<T> {
+    Decoder::new(Field {
+        name,
+        read,
+        reads_target: false,
+        custom: false,
+    })
+}
+
+pub fn type_() -> Decoder<String> {
+    field("type_", |event| Ok(event.type_()))
+}
+
+pub fn key() -> Decoder<String> {
+    field("key", |event| Ok(event.key()))
+}
+
+pub fn key_code() -> Decoder<u32> {
+    field("key_code", |event| Ok(event.key_code()))
+}
+
+/// The value of the input element the event is for
+pub fn target_value() -> Decoder<String> {
+    Decoder::new(Field {
+        name: "target_value",
+        read: |event| Ok(event.target_value()),
+        reads_target: true,
+        custom: false,
+    })
+}
+
+/// Whether the checkbox the event is for is checked
+pub fn target_checked() -> Decoder<bool> {
+    Decoder::new(Field {
+        name: "target_checked",
+        read: |event| Ok(event.target_checked()),
+        reads_target: true,
+        custom: false,
+    })
+}
+
+pub fn client_x() -> Decoder<i32> {
+    field("client_x", |event| Ok(event.client_x()))
+}
+
+pub fn client_y() -> Decoder<i32> {
+    field("client_y", |event| Ok(event.client_y()))
+}
+
+pub fn ctrl_key() -> Decoder<bool> {
+    field("ctrl_key", |event| Ok(event.ctrl_key()))
+}
+
+pub fn shift_key() -> Decoder<bool> {
+    field("shift_key", |event| Ok(event.shift_key()))
+}
+
+pub fn alt_key() -> Decoder<bool> {
+    field("alt_key", |event| Ok(event.alt_key()))
+}
+
+pub fn meta_key() -> Decoder<bool> {
+    field("meta_key", |event| Ok(event.meta_key()))
+}
+
+/// The `detail` of a custom event, deserialized from JSON
+pub fn detail<T: DeserializeOwned + 'static>() -> Decoder<T> {
+    field("detail", read_detail::<T>)
+}
+
+fn read_detail<T: DeserializeOwned>(event: &Event) -> Result<T, String> {
+    let detail = event
+        .detail()
+        .ok_or_else(|| "the event has no detail".to_owned())?;
+    serde_json::from_str(&detail).map_err(|err| err.to_string())
+}
+
+struct Field<T> {
+    name: &'static str,
+    read: fn(&Event) -> Result<T, String>,
+    reads_target: bool,
+    // Made by `Decoder::from_fn`, so `read` has to be compared as well
+    custom: bool,
+}
+
+impl<T> Decode<T> for Field<T> {
+    fn decode(&self, event: &Event) -> Result<T, String> {
+        (self.read)(event)
+    }
+
+    fn reads_target(&self) -> bool {
+        self.reads_target
+    }
+}
+
+impl<T> PartialEq for Field<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.custom == other.custom
+            && (!self.custom || fn_addr_eq(self.read, other.read))
+    }
+}
+
+impl<T> Debug for Field<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Succeed<T>(T);
+
+impl<T: Clone> Decode<T> for Succeed<T> {
+    fn decode(&self, _: &Event) -> Result<T, String> {
+        Ok(self.0.clone())
+    }
+
+    fn reads_target(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Fail(String);
+
+impl<T> Decode<T> for Fail {
+    fn decode(&self, _: &Event) -> Result<T, String> {
+        Err(self.0.clone())
+    }
+
+    fn reads_target(&self) -> bool {
+        false
+    }
+}
+
+struct Map<A, B> {
+    inner: Decoder<A>,
+    f: fn(A) -> B,
+}
+
+impl<A: 'static, B> Decode<B> for Map<A, B> {
+    fn decode(&self, event: &Event) -> Result<B, String> {
+        self.inner.decode(event).map(self.f)
+    }
+
+    fn reads_target(&self) -> bool {
+        self.inner.reads_target()
+    }
+}
+
+impl<A, B> PartialEq for Map<A, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && fn_addr_eq(self.f, other.f)
+    }
+}
+
+impl<A, B> Debug for Map<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Map").field(&self.inner).finish()
+    }
+}
+
+struct MapKeyed<A, B> {
+    inner: Decoder<A>,
+    f: RcEventClosure<A, B>,
+}
+
+impl<A: 'static, B> Decode<B> for MapKeyed<A, B> {
+    fn decode(&self, event: &Event) -> Result<B, String> {
+        self.inner
+            .decode(event)
+            .map(|value| self.f.0.call_ish(value))
+    }
+
+    fn reads_target(&self) -> bool {
+        self.inner.reads_target()
+    }
+}
+
+impl<A, B> PartialEq for MapKeyed<A, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && self.f == other.f
+    }
+}
+
+impl<A, B> Debug for MapKeyed<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MapKeyed")
+            .field(&self.inner)
+            .field(&self.f.0)
+            .finish()
+    }
+}
+
+struct Map2<A, B, C> {
+    a: Decoder<A>,
+    b: Decoder<B>,
+    f: fn(A, B) -> C,
+}
+
+impl<A: 'static, B: 'static, C> Decode<C> for Map2<A, B, C> {
+    fn decode(&self, event: &Event) -> Result<C, String> {
+        Ok((self.f)(self.a.decode(event)?, self.b.decode(event)?))
+    }
+
+    fn reads_target(&self) -> bool {
+        self.a.reads_target() || self.b.reads_target()
+    }
+}
+
+impl<A, B, C> PartialEq for Map2<A, B, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.a == other.a && self.b == other.b && fn_addr_eq(self.f, other.f)
+    }
+}
+
+impl<A, B, C> Debug for Map2<A, B, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Map2").field(&self.a).field(&self.b).finish()
+    }
+}
+
+struct AndThen<A, B> {
+    inner: Decoder<A>,
+    f: fn(A) -> Decoder<B>,
+}
+
+impl<A: 'static, B: 'static> Decode<B> for AndThen<A, B> {
+    fn decode(&self, event: &Event) -> Result<B, String> {
+        (self.f)(self.inner.decode(event)?).decode(event)
+    }
+
+    // The next decoder is not known before there is an event, so this is a guess
+    fn reads_target(&self) -> bool {
+        self.inner.reads_target()
+    }
+}
+
+impl<A, B> PartialEq for AndThen<A, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && fn_addr_eq(self.f, other.f)
+    }
+}
+
+impl<A, B> Debug for AndThen<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AndThen").field(&self.inner).finish()
+    }
+}
+
+struct Filter<T> {
+    inner: Decoder<T>,
+    predicate: fn(&T) -> bool,
+}
+
+impl<T: 'static> Decode<T> for Filter<T> {
+    fn decode(&self, event: &Event) -> Result<T, String> {
+        let value = self.inner.decode(event)?;
+        if (self.predicate)(&value) {
+            Ok(value)
+        } else {
+            Err("the value was filtered out".to_owned())
+        }
+    }
+
+    fn reads_target(&self) -> bool {
+        self.inner.reads_target()
+    }
+}
+
+impl<T> PartialEq for Filter<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && fn_addr_eq(self.predicate, other.predicate)
+    }
+}
+
+impl<T> Debug for Filter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Filter").field(&self.inner).finish()
+    }
+}
+
+// A decoder whose messages are converted for `Html::map`
+struct MapWith<A, B> {
+    inner: Decoder<A>,
+    mapper: MsgMapper<A, B>,
+}
+
+impl<A, B> PartialEq for MapWith<A, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && self.mapper == other.mapper
+    }
+}
+
+impl<A, B> Debug for MapWith<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MapWith").field(&self.inner).finish()
+    }
+}
+
+impl<A: 'static, B> Decode<B> for MapWith<A, B> {
+    fn decode(&self, event: &Event) -> Result<B, String> {
+        self.inner
+            .decode(event)
+            .map(|value| self.mapper.call(value))
+    }
+
+    fn reads_target(&self) -> bool {
+        self.inner.reads_target()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_x, detail, key, map2, shift_key, succeed, target_value, Decoder};
+    use crate::dom_event::{Event, EventData};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Clicked(i32),
+        Moved { x: i32, shift: bool },
+        Changed(Vec<u32>),
+        Submit,
+    }
+
+    fn is_enter(key: &String) -> bool {
+        key == "Enter"
+    }
+
+    fn event(data: EventData) -> Event {
+        Event::from_data(data)
+    }
+
+    #[test]
+    fn decodes_events() {
+        let click = event(EventData {
+            type_: "click".to_owned(),
+            client_x: 12,
+            shift_key: true,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            client_x().map(Msg::Clicked).decode(&click),
+            Ok(Msg::Clicked(12))
+        );
+        assert_eq!(
+            map2(client_x(), shift_key(), |x, shift| Msg::Moved { x, shift }).decode(&click),
+            Ok(Msg::Moved { x: 12, shift: true })
+        );
+
+        let on_enter = key().filter(is_enter).map_to(Msg::Submit);
+        assert!(on_enter.decode(&click).is_err());
+        let enter = event(EventData {
+            key: "Enter".to_owned(),
+            ..Default::default()
+        });
+        assert_eq!(on_enter.decode(&enter), Ok(Msg::Submit));
+
+        let custom = event(EventData {
+            detail: Some("[1, 2]".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(
+            detail().map(Msg::Changed).decode(&custom),
+            Ok(Msg::Changed(vec![1, 2]))
+        );
+        assert!(detail::<Vec<u32>>().decode(&click).is_err());
+    }
+
+    #[test]
+    fn decoders_can_be_compared() {
+        assert_eq!(client_x().map(Msg::Clicked), client_x().map(Msg::Clicked));
+        assert_ne!(
+            client_x().map(Msg::Clicked),
+            super::client_y().map(Msg::Clicked)
+        );
+        assert_eq!(
+            key().filter(is_enter).map_to(Msg::Submit),
+            key().filter(is_enter).map_to(Msg::Submit)
+        );
+        assert_ne!(succeed(Msg::Clicked(1)), succeed(Msg::Clicked(2)));
+        assert_ne!(key(), Decoder::from_fn("key", |event| Ok(event.key())));
+
+        let clicked = |offset: i32| client_x().map_keyed(offset, move |x| Msg::Clicked(x + offset));
+        assert_eq!(clicked(1), clicked(1));
+        assert_ne!(clicked(1), clicked(2));
+        assert_ne!(clicked(1), client_x().map_keyed(1, |x| Msg::Clicked(x - 1)));
+
+        assert!(target_value().map(|_| Msg::Submit).reads_target());
+        assert!(!key().map_to(Msg::Submit).reads_target());
+    }
+}
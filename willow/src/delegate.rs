@@ -0,0 +1,486 @@
+//! Event delegation: one listener per event type on the node the view is rendered into, instead
+//! of one for every handler. The target of an event is found in the last rendered tree, and the
+//! handlers from there up to the root are called in Rust, the way the event would have bubbled.
+//!
+//! Events that do not bubble, like `mouseenter` or `load`, still get a listener on the element.
+//! `focus` and `blur` are delegated through `focusin` and `focusout`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::slice;
+
+use crate::{
+    backend::Backend,
+    dom_event::Event,
+    html::{Attribute, EventOptions, Html, HtmlTag},
+    Error,
+};
+
+/// The listeners on the root of a view that is rendered with `render::render_delegated`.
+/// Listeners stay for as long as the delegation.
+pub struct Delegation<B: Backend> {
+    root: B::Node,
+    handler: Rc<Fn(Event)>,
+    listeners: RefCell<Vec<(String, B::Listener)>>,
+}
+
+impl<B: Backend> Delegation<B> {
+    /// Delegate the events below `root` to `handler`, which should find the messages for them
+    /// with `messages`
+    pub fn new<F>(root: B::Node, handler: F) -> Self
+    where
+        F: Fn(Event) + 'static,
+    {
+        Delegation {
+            root,
+            handler: Rc::new(handler),
+            listeners: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn root(&self) -> &B::Node {
+        &self.root
+    }
+
+    /// Make sure there is a listener on the root for events of `type_`
+    pub(crate) fn listen(&self, backend: &B, type_: &str) -> Result<(), Error> {
+        let mut listeners = self.listeners.borrow_mut();
+        if !listeners.iter().any(|(current, _)| current == type_) {
+            let listener = backend.add_listener(
+                &self.root,
+                type_,
+                &EventOptions::default(),
+                self.handler.clone(),
+            )?;
+            listeners.push((type_.to_owned(), listener));
+        }
+        Ok(())
+    }
+}
+
+/// The type to listen for on the root for events of `type_`, and whether the event bubbles up
+/// from the target. `None` for events that need a listener on the element itself, which
+/// includes listeners that capture, are passive or only listen once.
+pub(crate) fn delegated_type(type_: &str, options: &EventOptions) -> Option<(&'static str, bool)> {
+    if options.capture || options.passive || options.once {
+        return None;
+    }
+    let delegated = match type_ {
+        "focus" => ("focusin", false),
+        "blur" => ("focusout", false),
+        "click" => ("click", true),
+        "dblclick" => ("dblclick", true),
+        "contextmenu" => ("contextmenu", true),
+        "mousedown" => ("mousedown", true),
+        "mouseup" => ("mouseup", true),
+        "mousemove" => ("mousemove", true),
+        "mouseover" => ("mouseover", true),
+        "mouseout" => ("mouseout", true),
+        "pointerdown" => ("pointerdown", true),
+        "pointerup" => ("pointerup", true),
+        "pointermove" => ("pointermove", true),
+        "touchstart" => ("touchstart", true),
+        "touchend" => ("touchend", true),
+        "touchmove" => ("touchmove", true),
+        "wheel" => ("wheel", true),
+        "keydown" => ("keydown", true),
+        "keyup" => ("keyup", true),
+        "keypress" => ("keypress", true),
+        "input" => ("input", true),
+        "change" => ("change", true),
+        "submit" => ("submit", true),
+        "focusin" => ("focusin", true),
+        "focusout" => ("focusout", true),
+        _ => return None,
+    };
+    Some(delegated)
+}
+
+/// The messages for an event that reached `root`, from the handlers in `tree` that it would
+/// have bubbled through. The second value says whether the message should be rendered right
+/// away, see `Program::dispatch_immediately`.
+///
+/// `prevent_default` and `stop_propagation` of the handlers are applied to `event`.
+pub fn messages<B, Msg>(
+    backend: &B,
+    root: &B::Node,
+    tree: &Html<Msg>,
+    event: &Event,
+) -> Vec<(Msg, bool)>
+where
+    B: Backend,
+    Msg: Clone,
+{
+    let nodes = match backend
+        .event_target(event)
+        .and_then(|target| path_to(backend, root, target))
+    {
+        Some(path) => tags_at(tree, &path),
+        None => return vec![],
+    };
+
+    let type_ = event.type_();
+    let mut messages = vec![];
+    for (depth, tag) in nodes.iter().enumerate().rev() {
+        let is_target = depth + 1 == nodes.len();
+        let mut stopped = false;
+
+        for attr in &tag.attrs {
+            if let Attribute::Event {
+                type_: handler_type,
+                to_message,
+                options,
+                ..
+            } = attr
+            {
+                match delegated_type(handler_type, options) {
+                    Some((listened, bubbles)) if listened == type_ && (bubbles || is_target) => {}
+                    _ => continue,
+                }
+                if options.prevent_default {
+                    event.prevent_default();
+                }
+                stopped |= options.stop_propagation;
+                if let Some(message) = to_message.to_message(event) {
+                    messages.push((message, to_message.is_input()));
+                }
+            }
+        }
+
+        if stopped {
+            event.stop_propagation();
+            break;
+        }
+    }
+    messages
+}
+
+// The indexes of the children from `root` down to `node`, `None` if `node` is not below `root`
+fn path_to<B: Backend>(backend: &B, root: &B::Node, mut node: B::Node) -> Option<Vec<usize>> {
+    let mut path = vec![];
+    while node != *root {
+        let parent = backend.parent(&node)?;
+        let index = (0..backend.child_count(&parent))
+            .position(|index| backend.child(&parent, index).as_ref() == Some(&node))?;
+        path.push(index);
+        node = parent;
+    }
+    path.reverse();
+    Some(path)
+}
+
+// The elements of `tree` along `path`. Text nodes have no handlers, so the path ends before them.
+fn tags_at<'a, Msg>(tree: &'a Html<Msg>, path: &[usize]) -> Vec<&'a HtmlTag<Msg>> {
+    let mut tags = vec![];
+    let mut children = slice::from_ref(tree);
+    for index in path {
+        match children.get(*index) {
+            Some(Html::Tag(tag)) => {
+                tags.push(tag);
+                children = &tag.children;
+            }
+            _ => break,
+        }
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{messages, Delegation};
+    use crate::attributes::key;
+    use crate::backend::mock::{MockBackend, NodeId, Operation};
+    use crate::cmd::Dispatcher;
+    use crate::decode;
+    use crate::dom_event::{Event, EventData};
+    use crate::events::{on, on_blur, on_click};
+    use crate::html::{a, button, div, input, li, span, text, ul, Html};
+    use crate::render::render_delegated;
+
+    // A view rendered with delegation into a mock DOM, which keeps the messages and events
+    // that reach the root
+    struct Delegated {
+        backend: Rc<MockBackend>,
+        tree: Rc<RefCell<Html<String>>>,
+        delegation: Delegation<MockBackend>,
+        received: Rc<RefCell<Vec<String>>>,
+        events: Rc<RefCell<Vec<Event>>>,
+    }
+
+    impl Delegated {
+        fn new(tree: Html<String>) -> Self {
+            let backend = Rc::new(MockBackend::new());
+            let root = backend.root();
+            let tree = Rc::new(RefCell::new(tree));
+            let received = Rc::new(RefCell::new(vec![]));
+            let events = Rc::new(RefCell::new(vec![]));
+            let delegation = {
+                let (backend, tree) = (backend.clone(), tree.clone());
+                let (received, events) = (received.clone(), events.clone());
+                Delegation::new(root, move |event| {
+                    let messages = messages(&*backend, &root, &tree.borrow(), &event);
+                    received
+                        .borrow_mut()
+                        .extend(messages.into_iter().map(|(message, _)| message));
+                    events.borrow_mut().push(event);
+                })
+            };
+            let delegated = Delegated {
+                backend,
+                tree,
+                delegation,
+                received,
+                events,
+            };
+            delegated.render(None);
+            delegated
+        }
+
+        fn render(&self, old: Option<&Html<String>>) {
+            render_delegated(
+                &*self.backend,
+                &Dispatcher::new(|_: String| {}),
+                &self.delegation,
+                &mut self.tree.borrow_mut(),
+                old,
+            )
+            .unwrap();
+        }
+
+        fn update(&self, tree: Html<String>) {
+            let old = self.tree.replace(tree);
+            self.render(Some(&old));
+        }
+
+        // The node at `path` below the root
+        fn node(&self, path: &[usize]) -> NodeId {
+            path.iter().fold(self.backend.root(), |node, index| {
+                self.backend.children(node)[*index]
+            })
+        }
+
+        // Dispatch an event of `type_` to the node at `path`, and take the messages it gave
+        fn dispatch(&self, path: &[usize], type_: &str) -> Vec<String> {
+            self.backend.dispatch_event(
+                self.node(path),
+                EventData {
+                    type_: type_.to_owned(),
+                    ..Default::default()
+                },
+            );
+            self.received.replace(vec![])
+        }
+
+        fn last_event(&self) -> Event {
+            self.events.borrow().last().cloned().unwrap()
+        }
+    }
+
+    fn view(items: &[&str]) -> Html<String> {
+        ul(
+            &[on_click("list".to_owned())],
+            &items
+                .iter()
+                .map(|item| {
+                    li(
+                        &[on_click(format!("item {}", item))],
+                        &[
+                            button(
+                                &[on_click(format!("remove {}", item)).stop_propagation()],
+                                &[text("x")],
+                            ),
+                            div(&[on_blur(format!("blur {}", item))], &[text(item)]),
+                        ],
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn handles_events_on_the_root() {
+        let backend = Rc::new(MockBackend::new());
+        let root = backend.root();
+        let tree = Rc::new(RefCell::new(view(&["a", "b"])));
+        let received = Rc::new(RefCell::new(vec![]));
+
+        let delegation = {
+            let (backend, tree, received) = (backend.clone(), tree.clone(), received.clone());
+            Delegation::new(root, move |event| {
+                let messages = messages(&*backend, &root, &tree.borrow(), &event);
+                received.borrow_mut().extend(messages);
+            })
+        };
+        let dispatcher = Dispatcher::new(|_: String| {});
+
+        render_delegated(
+            &*backend,
+            &dispatcher,
+            &delegation,
+            &mut tree.borrow_mut(),
+            None,
+        )
+        .unwrap();
+        let listeners = backend
+            .operations()
+            .into_iter()
+            .filter(|operation| match operation {
+                Operation::AddListener(node, ..) => *node == root,
+                _ => false,
+            })
+            .count();
+        // One for clicks, and one for blurs through `focusout`
+        assert_eq!(listeners, 2);
+
+        let click = |node| {
+            backend.dispatch_event(
+                node,
+                EventData {
+                    type_: "click".to_owned(),
+                    ..Default::default()
+                },
+            );
+        };
+        let list = backend.children(root)[0];
+        let second = backend.children(list)[1];
+        click(second);
+        click(backend.children(second)[0]);
+        backend.dispatch_event(
+            backend.children(second)[1],
+            EventData {
+                type_: "focusout".to_owned(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            *received.borrow(),
+            vec![
+                ("item b".to_owned(), false),
+                ("list".to_owned(), false),
+                ("remove b".to_owned(), false),
+                ("blur b".to_owned(), false),
+            ]
+        );
+
+        // Handlers are read from the current tree, so updating them needs nothing from the DOM
+        let mut new_tree = view(&["a", "c"]);
+        backend.clear_operations();
+        render_delegated(
+            &*backend,
+            &dispatcher,
+            &delegation,
+            &mut new_tree,
+            Some(&tree.borrow()),
+        )
+        .unwrap();
+        tree.replace(new_tree);
+        assert_eq!(
+            backend.operations(),
+            vec![Operation::SetText(
+                backend.children(backend.children(second)[1])[0],
+                "c".to_owned()
+            )]
+        );
+        received.borrow_mut().clear();
+        click(second);
+        assert_eq!(
+            *received.borrow(),
+            vec![("item c".to_owned(), false), ("list".to_owned(), false)]
+        );
+    }
+
+    #[test]
+    fn listeners_with_options_stay_on_the_element() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let delegation = Delegation::new(root, |_| {});
+        let dispatcher = Dispatcher::new(|_: String| {});
+
+        let mut tree: Html<String> = div(&[on_click("clicked".to_owned()).capture()], &[]);
+        render_delegated(&backend, &dispatcher, &delegation, &mut tree, None).unwrap();
+
+        assert_eq!(backend.listener_count(root), 0);
+        assert_eq!(backend.listener_count(backend.children(root)[0]), 1);
+    }
+
+    #[test]
+    fn stopping_propagation_skips_the_handlers_above() {
+        let delegated = Delegated::new(div(
+            &[on_click("outer".to_owned())],
+            &[div(
+                &[
+                    on_click("middle".to_owned()).stop_propagation(),
+                    on_click("also middle".to_owned()),
+                ],
+                &[span(&[on_click("inner".to_owned())], &[])],
+            )],
+        ));
+
+        assert_eq!(
+            delegated.dispatch(&[0, 0, 0], "click"),
+            vec!["inner", "middle", "also middle"]
+        );
+        assert!(delegated.last_event().propagation_stopped());
+        assert_eq!(delegated.dispatch(&[0], "click"), vec!["outer"]);
+        assert!(!delegated.last_event().propagation_stopped());
+    }
+
+    #[test]
+    fn prevents_the_default_of_events_through_handlers_that_ask_for_it() {
+        let delegated = Delegated::new(div(
+            &[on_click("outer".to_owned())],
+            &[a(&[on_click("link".to_owned()).prevent_default()], &[])],
+        ));
+
+        assert_eq!(delegated.dispatch(&[0, 0], "click"), vec!["link", "outer"]);
+        assert!(delegated.last_event().default_prevented());
+        assert_eq!(delegated.dispatch(&[0], "click"), vec!["outer"]);
+        assert!(!delegated.last_event().default_prevented());
+    }
+
+    #[test]
+    fn focus_and_blur_are_delegated_without_bubbling() {
+        let focus = |message: &str| on("focus", decode::succeed(message.to_owned()));
+        let delegated = Delegated::new(div(
+            &[focus("outer"), on_blur("outer blur".to_owned())],
+            &[input(&[focus("input")], &[])],
+        ));
+
+        // One listener for `focusin` and one for `focusout`, both on the root
+        assert_eq!(
+            delegated.backend.listener_count(delegated.backend.root()),
+            2
+        );
+        assert_eq!(delegated.backend.listener_count(delegated.node(&[0, 0])), 0);
+        assert_eq!(delegated.dispatch(&[0, 0], "focusin"), vec!["input"]);
+        assert_eq!(
+            delegated.dispatch(&[0, 0], "focusout"),
+            Vec::<String>::new()
+        );
+        assert_eq!(delegated.dispatch(&[0], "focusout"), vec!["outer blur"]);
+    }
+
+    #[test]
+    fn keyed_nodes_keep_their_handlers_when_they_move() {
+        let list = |items: &[&str]| -> Html<String> {
+            ul(
+                &[],
+                &items
+                    .iter()
+                    .map(|item| li(&[key(item.to_string()), on_click(item.to_string())], &[]))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let delegated = Delegated::new(list(&["a", "b"]));
+        let b = delegated.node(&[0, 1]);
+
+        delegated.update(list(&["b", "c"]));
+
+        assert_eq!(delegated.node(&[0, 0]), b);
+        assert_eq!(delegated.dispatch(&[0, 0], "click"), vec!["b"]);
+        assert_eq!(delegated.dispatch(&[0, 1], "click"), vec!["c"]);
+    }
+}
@@ -0,0 +1,519 @@
+//! Compare two views and list the changes needed to go from one to the other, without touching
+//! the DOM. `render::apply` applies the changes.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::html::{Attribute, Html, ListenerHandle};
+
+/// Where a node is, as the index of each child on the way down from the node the view is
+/// rendered into. The root of the view is `[0]`.
+pub type Path = Vec<usize>;
+
+/// A change to the DOM. Patches are applied in order, and every path refers to the DOM as it is
+/// after the patches before it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Patch<Msg> {
+    /// Create `html` and insert it as child `index` of `parent`
+    Insert {
+        parent: Path,
+        index: usize,
+        html: Html<Msg>,
+    },
+    Replace {
+        path: Path,
+        html: Html<Msg>,
+    },
+    Remove {
+        path: Path,
+    },
+    /// Take out child `from` of `parent`, and put it back so that it ends up as child `to`
+    Move {
+        parent: Path,
+        from: usize,
+        to: usize,
+    },
+    SetText {
+        path: Path,
+        text: String,
+    },
+    AddAttribute {
+        path: Path,
+        attribute: Attribute<Msg>,
+    },
+    RemoveAttribute {
+        path: Path,
+        attribute: Attribute<Msg>,
+    },
+    /// Keep the listener of an event, but make the messages with `attribute` from now on. The
+    /// listener is handed over to `attribute` by the `KeepListener` before it.
+    UpdateListener {
+        path: Path,
+        attribute: Attribute<Msg>,
+    },
+    /// Hand the listener of an event that is in both views over from `from` to `to`, so that it
+    /// is not dropped with the old view. Nothing changes in the DOM.
+    KeepListener {
+        from: ListenerHandle,
+        to: ListenerHandle,
+    },
+}
+
+/// The patches that turn the DOM rendered from `old` into `new`. Neither view is changed.
+pub fn diff<Msg: PartialEq + Clone>(old: &Html<Msg>, new: &Html<Msg>) -> Vec<Patch<Msg>> {
+    let mut patches = vec![];
+    diff_node(&mut patches, &mut vec![0], old, new);
+    patches
+}
+
+fn diff_node<Msg: PartialEq + Clone>(
+    patches: &mut Vec<Patch<Msg>>,
+    path: &mut Path,
+    old: &Html<Msg>,
+    new: &Html<Msg>,
+) {
+    match (old, new) {
+        (Html::Tag(old_tag), Html::Tag(new_tag))
+            if old_tag.tag == new_tag.tag && old_tag.key() == new_tag.key() =>
+        {
+            // We start by removing the attributes that are no longer active. Events where only
+            // the message changed keep their listener, which is updated instead.
+            let matches = match_attributes(&old_tag.attrs, &new_tag.attrs);
+            let mut added = vec![true; new_tag.attrs.len()];
+            for (attr, matched) in old_tag.attrs.iter().zip(matches) {
+                let new_attr = match matched {
+                    Some(index) => {
+                        added[index] = false;
+                        &new_tag.attrs[index]
+                    }
+                    None => {
+                        patches.push(Patch::RemoveAttribute {
+                            path: path.clone(),
+                            attribute: attr.clone(),
+                        });
+                        continue;
+                    }
+                };
+
+                // Listeners are kept for the new events because we do not want them to be
+                // dropped
+                if let (Some(from), Some(to)) = (attr.listener(), new_attr.listener()) {
+                    patches.push(Patch::KeepListener {
+                        from: from.clone(),
+                        to: to.clone(),
+                    });
+                    if new_attr != attr {
+                        patches.push(Patch::UpdateListener {
+                            path: path.clone(),
+                            attribute: new_attr.clone(),
+                        });
+                    }
+                }
+            }
+            // Then we add the ones that are added
+            for (attr, added) in new_tag.attrs.iter().zip(added) {
+                if added {
+                    patches.push(Patch::AddAttribute {
+                        path: path.clone(),
+                        attribute: attr.clone(),
+                    });
+                }
+            }
+
+            diff_children(patches, path, &old_tag.children, &new_tag.children);
+        }
+        (Html::Text(s1), Html::Text(s2)) => {
+            if s1 != s2 {
+                patches.push(Patch::SetText {
+                    path: path.clone(),
+                    text: s2.clone(),
+                });
+            }
+        }
+        _ => patches.push(Patch::Replace {
+            path: path.clone(),
+            html: new.clone(),
+        }),
+    }
+}
+
+/// The index of the new attribute each old one is kept as, if any. Every new attribute is
+/// matched once. Equal attributes are matched first, and then events that can keep their
+/// listener.
+fn match_attributes<Msg: PartialEq>(
+    old: &[Attribute<Msg>],
+    new: &[Attribute<Msg>],
+) -> Vec<Option<usize>> {
+    let mut taken = vec![false; new.len()];
+    let mut matches = old
+        .iter()
+        .map(|attr| take_first(&mut taken, new, |new_attr| new_attr == attr))
+        .collect::<Vec<_>>();
+    for (attr, matched) in old.iter().zip(&mut matches) {
+        if matched.is_none() {
+            *matched = take_first(&mut taken, new, |new_attr| attr.shares_listener(new_attr));
+        }
+    }
+    matches
+}
+
+// The first of `attrs` that is not taken and `f` is true for, which is then taken
+fn take_first<Msg, F>(taken: &mut [bool], attrs: &[Attribute<Msg>], f: F) -> Option<usize>
+where
+    F: Fn(&Attribute<Msg>) -> bool,
+{
+    let index = (0..attrs.len()).find(|index| !taken[*index] && f(&attrs[*index]))?;
+    taken[index] = true;
+    Some(index)
+}
+
+/// Diff the children of the node at `path`. The children are first added, removed and moved, so
+/// that they are in their final positions before their own patches.
+fn diff_children<Msg: PartialEq + Clone>(
+    patches: &mut Vec<Patch<Msg>>,
+    path: &mut Path,
+    old: &[Html<Msg>],
+    new: &[Html<Msg>],
+) {
+    let sources = match (keys(old), keys(new)) {
+        (Some(old_keys), Some(new_keys)) => {
+            move_keyed_children(patches, path, &old_keys, new, &new_keys)
+        }
+        _ => {
+            // Remove from the end, so that the indexes before are still right
+            for index in (new.len()..old.len()).rev() {
+                path.push(index);
+                patches.push(Patch::Remove { path: path.clone() });
+                path.pop();
+            }
+            for (index, child) in new.iter().enumerate().skip(old.len()) {
+                patches.push(Patch::Insert {
+                    parent: path.clone(),
+                    index,
+                    html: child.clone(),
+                });
+            }
+            (0..new.len())
+                .map(|index| if index < old.len() { Some(index) } else { None })
+                .collect()
+        }
+    };
+
+    for (index, (source, new_child)) in sources.iter().zip(new).enumerate() {
+        if let Some(old_index) = source {
+            path.push(index);
+            diff_node(patches, path, &old[*old_index], new_child);
+            path.pop();
+        }
+    }
+}
+
+/// Put children where every child has a unique key in their new order. Children are matched by
+/// key, so a child that has moved is moved instead of every sibling after it being rewritten.
+/// Returns the index of the old child for each new child.
+fn move_keyed_children<Msg: Clone>(
+    patches: &mut Vec<Patch<Msg>>,
+    path: &mut Path,
+    old_keys: &[&str],
+    new: &[Html<Msg>],
+    new_keys: &[&str],
+) -> Vec<Option<usize>> {
+    let old_indexes = old_keys
+        .iter()
+        .enumerate()
+        .map(|(index, key)| (*key, index))
+        .collect::<HashMap<_, _>>();
+    // For each new child, the index of the old child with the same key
+    let sources = new_keys
+        .iter()
+        .map(|key| old_indexes.get(key).cloned())
+        .collect::<Vec<_>>();
+
+    let new_keys_set = new_keys.iter().collect::<HashSet<_>>();
+    for (index, key) in old_keys.iter().enumerate().rev() {
+        if !new_keys_set.contains(key) {
+            path.push(index);
+            patches.push(Patch::Remove { path: path.clone() });
+            path.pop();
+        }
+    }
+
+    // The children that make up the longest increasing run of old indexes are already in the
+    // right order relative to each other, so only the others have to be moved
+    let (kept, kept_sources): (Vec<_>, Vec<_>) = sources
+        .iter()
+        .enumerate()
+        .filter_map(|(index, source)| source.map(|source| (index, source)))
+        .unzip();
+    let stable = longest_increasing_subsequence(&kept_sources)
+        .into_iter()
+        .map(|index| kept[index])
+        .collect::<HashSet<_>>();
+
+    // The old index of each child as they are after the removals, `None` for inserted children
+    let mut current = old_keys
+        .iter()
+        .enumerate()
+        .filter(|(_, key)| new_keys_set.contains(key))
+        .map(|(index, _)| Some(index))
+        .collect::<Vec<_>>();
+
+    // Go backwards, so that the next sibling is always in its final position
+    let mut next_sibling = current.len();
+    for (index, new_child) in new.iter().enumerate().rev() {
+        match sources[index] {
+            Some(old_index) => {
+                let from = current
+                    .iter()
+                    .position(|source| *source == Some(old_index))
+                    .expect("kept children are in the list");
+                if stable.contains(&index) {
+                    next_sibling = from;
+                } else {
+                    let to = if from < next_sibling {
+                        next_sibling - 1
+                    } else {
+                        next_sibling
+                    };
+                    current.remove(from);
+                    current.insert(to, Some(old_index));
+                    patches.push(Patch::Move {
+                        parent: path.clone(),
+                        from,
+                        to,
+                    });
+                    next_sibling = to;
+                }
+            }
+            None => {
+                current.insert(next_sibling, None);
+                patches.push(Patch::Insert {
+                    parent: path.clone(),
+                    index: next_sibling,
+                    html: new_child.clone(),
+                });
+            }
+        }
+    }
+
+    sources
+}
+
+/// The keys of `children`, if every child has one and they are unique
+fn keys<Msg>(children: &[Html<Msg>]) -> Option<Vec<&str>> {
+    let mut keys = Vec::with_capacity(children.len());
+    let mut seen = HashSet::new();
+
+    for child in children {
+        let key = match child {
+            Html::Tag(tag) => tag.key()?,
+            Html::Text(_) => return None,
+        };
+
+        if !seen.insert(key) {
+            #[cfg(debug_assertions)]
+            console_log!(
+                "WARN: Duplicate key {:?} among siblings, falling back to diffing by index",
+                key
+            );
+            return None;
+        }
+        keys.push(key);
+    }
+
+    Some(keys)
+}
+
+/// The indexes of the values that make up the longest strictly increasing subsequence of
+/// `values`
+fn longest_increasing_subsequence(values: &[usize]) -> Vec<usize> {
+    // For each item, the index of the item before it in the longest subsequence ending there
+    let mut predecessors = vec![0; values.len()];
+    // tails[n] is the index of the smallest value that ends an increasing subsequence of
+    // length n + 1
+    let mut tails: Vec<usize> = vec![];
+
+    for (index, value) in values.iter().enumerate() {
+        let length = match tails.binary_search_by(|tail| values[*tail].cmp(value)) {
+            Ok(length) | Err(length) => length,
+        };
+        if length > 0 {
+            predecessors[index] = tails[length - 1];
+        }
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+
+    let mut result = vec![0; tails.len()];
+    if let Some(last) = tails.last() {
+        let mut index = *last;
+        for slot in result.iter_mut().rev() {
+            *slot = index;
+            index = predecessors[index];
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, longest_increasing_subsequence, Patch};
+    use crate::attributes::{class, key};
+    use crate::events::{on_click, on_double_click};
+    use crate::html::{li, text, ul, Html, ListenerHandle};
+
+    fn list(items: &[&str]) -> Html<()> {
+        ul(
+            &[],
+            &items
+                .iter()
+                .map(|item| li(&[key(item.to_string())], &[text(item)]))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn listeners<Msg>(html: &Html<Msg>) -> Vec<ListenerHandle> {
+        match html {
+            Html::Tag(tag) => tag
+                .attrs
+                .iter()
+                .filter_map(|attr| attr.listener().cloned())
+                .collect(),
+            Html::Text(_) => vec![],
+        }
+    }
+
+    #[test]
+    fn longest_increasing_subsequence_finds_unmoved_items() {
+        assert_eq!(longest_increasing_subsequence(&[]), Vec::<usize>::new());
+        assert_eq!(longest_increasing_subsequence(&[0, 1, 2]), vec![0, 1, 2]);
+        // The last item moved to the front
+        assert_eq!(longest_increasing_subsequence(&[3, 0, 1, 2]), vec![1, 2, 3]);
+        // The first item moved to the back
+        assert_eq!(longest_increasing_subsequence(&[1, 2, 3, 0]), vec![0, 1, 2]);
+        assert_eq!(longest_increasing_subsequence(&[4, 1, 3, 0, 2, 5]).len(), 3);
+    }
+
+    #[test]
+    fn diffs_attributes_text_and_unkeyed_children() {
+        let old: Html<()> = ul(
+            &[class("a")],
+            &[li(&[], &[text("1")]), li(&[], &[]), li(&[], &[])],
+        );
+        let new: Html<()> = ul(&[class("b")], &[li(&[], &[text("2")])]);
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Patch::RemoveAttribute {
+                    path: vec![0],
+                    attribute: class("a"),
+                },
+                Patch::AddAttribute {
+                    path: vec![0],
+                    attribute: class("b"),
+                },
+                Patch::Remove { path: vec![0, 2] },
+                Patch::Remove { path: vec![0, 1] },
+                Patch::SetText {
+                    path: vec![0, 0, 0],
+                    text: "2".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_keyed_children() {
+        assert_eq!(
+            diff(&list(&["a", "b", "c", "d"]), &list(&["d", "a", "b", "c"])),
+            vec![Patch::Move {
+                parent: vec![0],
+                from: 3,
+                to: 0,
+            }]
+        );
+
+        assert_eq!(
+            diff(&list(&["a", "b", "c"]), &list(&["c", "x", "a"])),
+            vec![
+                Patch::Remove { path: vec![0, 1] },
+                Patch::Insert {
+                    parent: vec![0],
+                    index: 0,
+                    html: li(&[key("x".to_owned())], &[text("x")]),
+                },
+                Patch::Move {
+                    parent: vec![0],
+                    from: 2,
+                    to: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn updates_listeners_when_only_the_message_changed() {
+        let old: Html<i32> = ul(&[on_click(1), on_double_click(1)], &[]);
+        let new: Html<i32> = ul(&[on_click(2), on_double_click(1)], &[]);
+
+        let (from, to) = (listeners(&old), listeners(&new));
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Patch::KeepListener {
+                    from: from[0].clone(),
+                    to: to[0].clone(),
+                },
+                Patch::UpdateListener {
+                    path: vec![0],
+                    attribute: on_click(2),
+                },
+                Patch::KeepListener {
+                    from: from[1].clone(),
+                    to: to[1].clone(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_the_listeners_to_the_renderer() {
+        let old: Html<i32> = ul(&[on_click(1)], &[]);
+        let new: Html<i32> = ul(&[on_click(1)], &[]);
+        listeners(&old)[0].set(1);
+
+        diff(&old, &new);
+
+        assert_eq!(listeners(&old)[0].get::<i32>(), Some(1));
+        assert_eq!(listeners(&new)[0].get::<i32>(), None);
+    }
+
+    #[test]
+    fn matches_each_attribute_once() {
+        let old: Html<i32> = ul(&[on_click(1), on_click(1)], &[]);
+        let new: Html<i32> = ul(&[on_click(1), on_click(2), on_click(1)], &[]);
+        let (from, to) = (listeners(&old), listeners(&new));
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Patch::KeepListener {
+                    from: from[0].clone(),
+                    to: to[0].clone(),
+                },
+                Patch::KeepListener {
+                    from: from[1].clone(),
+                    to: to[2].clone(),
+                },
+                Patch::AddAttribute {
+                    path: vec![0],
+                    attribute: on_click(2),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! The parts of a DOM event that messages are made from. In the browser this reads from the
+//! real `web_sys::Event`, natively it is backed by `EventData`, so events can be simulated in
+//! tests.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "web")]
+use wasm_bindgen::JsCast;
+
+/// The fields of a simulated event
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventData {
+    pub type_: String,
+    // `key` and `key_code` of keyboard events
+    pub key: String,
+    pub key_code: u32,
+    // The value and `checked` of the target, for input events
+    pub value: String,
+    pub checked: bool,
+    // The position of mouse events
+    pub client_x: i32,
+    pub client_y: i32,
+    pub ctrl_key: bool,
+    pub shift_key: bool,
+    pub alt_key: bool,
+    pub meta_key: bool,
+    // The `detail` of a custom event, as JSON
+    pub detail: Option<String>,
+}
+
+/// Whether events of `type_` bubble up to the ancestors of their target
+pub fn bubbles(type_: &str) -> bool {
+    !matches!(
+        type_,
+        "focus"
+            | "blur"
+            | "mouseenter"
+            | "mouseleave"
+            | "pointerenter"
+            | "pointerleave"
+            | "load"
+            | "unload"
+            | "error"
+            | "abort"
+            | "scroll"
+    )
+}
+
+#[derive(Clone)]
+pub struct Event(Inner);
+
+#[derive(Clone)]
+enum Inner {
+    #[cfg(feature = "web")]
+    Web(web_sys::Event),
+    Data(EventData, Rc<Handled>),
+}
+
+// What the listeners of a simulated event did with it, shared by the clones of the event
+#[derive(Default)]
+struct Handled {
+    default_prevented: Cell<bool>,
+    propagation_stopped: Cell<bool>,
+}
+
+impl Event {
+    pub fn from_data(data: EventData) -> Self {
+        Event(Inner::Data(data, Default::default()))
+    }
+
+    pub fn type_(&self) -> String {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event.type_(),
+            Inner::Data(data, _) => data.type_.clone(),
+        }
+    }
+
+    /// The `key` of a keyboard event, empty for other events
+    pub fn key(&self) -> String {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event
+                .dyn_ref::<web_sys::KeyboardEvent>()
+                .map(|event| event.key())
+                .unwrap_or_default(),
+            Inner::Data(data, _) => data.key.clone(),
+        }
+    }
+
+    /// The `keyCode` of a keyboard event, 0 for other events
+    pub fn key_code(&self) -> u32 {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event
+                .dyn_ref::<web_sys::KeyboardEvent>()
+                .map(|event| event.key_code())
+                .unwrap_or(0),
+            Inner::Data(data, _) => data.key_code,
+        }
+    }
+
+    /// The value of the target, if it is an input element
+    pub fn target_value(&self) -> String {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event
+                .target()
+                .and_then(|target| target.dyn_into::<web_sys::HtmlInputElement>().ok())
+                .map(|el| el.value())
+                .unwrap_or_default(),
+            Inner::Data(data, _) => data.value.clone(),
+        }
+    }
+
+    /// Whether the target is checked, if it is a checkbox or a radio button
+    pub fn target_checked(&self) -> bool {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event
+                .target()
+                .and_then(|target| target.dyn_into::<web_sys::HtmlInputElement>().ok())
+                .map(|el| el.checked())
+                .unwrap_or(false),
+            Inner::Data(data, _) => data.checked,
+        }
+    }
+
+    /// The horizontal position of a mouse event in the viewport, 0 for other events
+    pub fn client_x(&self) -> i32 {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event
+                .dyn_ref::<web_sys::MouseEvent>()
+                .map(|event| event.client_x())
+                .unwrap_or(0),
+            Inner::Data(data, _) => data.client_x,
+        }
+    }
+
+    /// The vertical position of a mouse event in the viewport, 0 for other events
+    pub fn client_y(&self) -> i32 {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event
+                .dyn_ref::<web_sys::MouseEvent>()
+                .map(|event| event.client_y())
+                .unwrap_or(0),
+            Inner::Data(data, _) => data.client_y,
+        }
+    }
+
+    pub fn ctrl_key(&self) -> bool {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => modifier(event, "ctrlKey"),
+            Inner::Data(data, _) => data.ctrl_key,
+        }
+    }
+
+    pub fn shift_key(&self) -> bool {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => modifier(event, "shiftKey"),
+            Inner::Data(data, _) => data.shift_key,
+        }
+    }
+
+    pub fn alt_key(&self) -> bool {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => modifier(event, "altKey"),
+            Inner::Data(data, _) => data.alt_key,
+        }
+    }
+
+    pub fn meta_key(&self) -> bool {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => modifier(event, "metaKey"),
+            Inner::Data(data, _) => data.meta_key,
+        }
+    }
+
+    /// The `detail` of a custom event as JSON, `None` for other events
+    pub fn detail(&self) -> Option<String> {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event
+                .dyn_ref::<web_sys::CustomEvent>()
+                .and_then(|event| js_sys::JSON::stringify(&event.detail()).ok())
+                .map(String::from),
+            Inner::Data(data, _) => data.detail.clone(),
+        }
+    }
+
+    /// The fields messages are made from, to send the event somewhere else
+    pub fn to_data(&self) -> EventData {
+        EventData {
+            type_: self.type_(),
+            key: self.key(),
+            key_code: self.key_code(),
+            value: self.target_value(),
+            checked: self.target_checked(),
+            client_x: self.client_x(),
+            client_y: self.client_y(),
+            ctrl_key: self.ctrl_key(),
+            shift_key: self.shift_key(),
+            alt_key: self.alt_key(),
+            meta_key: self.meta_key(),
+            detail: self.detail(),
+        }
+    }
+
+    /// Simulated events have no default action, so for them this is only remembered, see
+    /// `default_prevented`
+    pub fn prevent_default(&self) {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event.prevent_default(),
+            Inner::Data(_, handled) => handled.default_prevented.set(true),
+        }
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event.default_prevented(),
+            Inner::Data(_, handled) => handled.default_prevented.get(),
+        }
+    }
+
+    /// Keep the event from reaching the listeners of the ancestors of the current node. For
+    /// simulated events it is up to the one dispatching them, see `propagation_stopped`.
+    pub fn stop_propagation(&self) {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event.stop_propagation(),
+            Inner::Data(_, handled) => handled.propagation_stopped.set(true),
+        }
+    }
+
+    pub fn propagation_stopped(&self) -> bool {
+        match &self.0 {
+            #[cfg(feature = "web")]
+            Inner::Web(event) => event.cancel_bubble(),
+            Inner::Data(_, handled) => handled.propagation_stopped.get(),
+        }
+    }
+
+    /// The underlying browser event, if this is not simulated
+    #[cfg(feature = "web")]
+    pub fn as_web(&self) -> Option<&web_sys::Event> {
+        match &self.0 {
+            Inner::Web(event) => Some(event),
+            Inner::Data(..) => None,
+        }
+    }
+}
+
+#[cfg(feature = "web")]
+impl From<web_sys::Event> for Event {
+    fn from(event: web_sys::Event) -> Self {
+        Event(Inner::Web(event))
+    }
+}
+
+// Keyboard and mouse events both have the modifier keys, but do not share an interface for them
+#[cfg(feature = "web")]
+fn modifier(event: &web_sys::Event, name: &str) -> bool {
+    js_sys::Reflect::get(event, &name.into())
+        .ok()
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+}
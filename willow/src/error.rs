@@ -0,0 +1,44 @@
+use std::fmt;
+
+#[cfg(feature = "web")]
+use wasm_bindgen::JsValue;
+
+/// An error from running a command
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    // An exception thrown by a browser API
+    #[cfg(feature = "web")]
+    Js(JsValue),
+    Message(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "web")]
+            Error::Js(value) => write!(f, "{:?}", value),
+            Error::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(feature = "web")]
+impl From<JsValue> for Error {
+    fn from(value: JsValue) -> Self {
+        Error::Js(value)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Message(message)
+    }
+}
+
+impl<'a> From<&'a str> for Error {
+    fn from(message: &'a str) -> Self {
+        Error::Message(message.to_owned())
+    }
+}
@@ -2,52 +2,62 @@ use std::default::Default;
 use std::fmt::Debug;
 use std::rc::Rc;
 
-use wasm_bindgen::JsCast;
+use crate::decode::{self, Decoder};
+use crate::html::{Attribute, EventClosureImpl, EventOptions, EventToMessage, RcEventClosure};
 
-use crate::html::{Attribute, EventClosureImpl, EventToMessage, RcEventClosure};
-
-pub fn on_click<Msg: Clone + 'static>(message: Msg) -> Attribute<Msg> {
+/// Listen for events of `type_`, and dispatch the message `decoder` gives for them. Events the
+/// decoder fails for are ignored. See `Attribute::prevent_default` and friends for the options
+/// of the listener.
+pub fn on<Msg>(type_: &str, decoder: Decoder<Msg>) -> Attribute<Msg> {
     Attribute::Event {
-        type_: "click".to_owned(),
-        to_message: EventToMessage::StaticMsg(message),
-        stop_propagation: false,
-        prevent_default: false,
-        js_closure: Default::default(),
+        type_: type_.to_owned(),
+        to_message: EventToMessage::Decoder(decoder),
+        options: EventOptions::default(),
+        listener: Default::default(),
     }
 }
 
-pub fn on_double_click<Msg: Clone + 'static>(message: Msg) -> Attribute<Msg> {
-    Attribute::Event {
-        type_: "dblclick".to_owned(),
-        to_message: EventToMessage::StaticMsg(message),
-        stop_propagation: false,
-        prevent_default: false,
-        js_closure: Default::default(),
-    }
+pub fn on_click<Msg: PartialEq + Debug + Clone + 'static>(message: Msg) -> Attribute<Msg> {
+    on("click", decode::succeed(message))
 }
 
-pub fn on_blur<Msg: Clone + 'static>(message: Msg) -> Attribute<Msg> {
-    Attribute::Event {
-        type_: "blur".to_owned(),
-        to_message: EventToMessage::StaticMsg(message),
-        stop_propagation: false,
-        prevent_default: false,
-        js_closure: Default::default(),
-    }
+pub fn on_double_click<Msg: PartialEq + Debug + Clone + 'static>(message: Msg) -> Attribute<Msg> {
+    on("dblclick", decode::succeed(message))
+}
+
+pub fn on_blur<Msg: PartialEq + Debug + Clone + 'static>(message: Msg) -> Attribute<Msg> {
+    on("blur", decode::succeed(message))
 }
 
-// TODO: Ensure that when we start using animationFrame, on_input gets special treatement
 pub fn on_input<Msg: 'static>(message: fn(String) -> Msg) -> Attribute<Msg> {
+    on("input", decode::target_value().map(message)).stop_propagation()
+}
+
+/// Dispatch the message for whether a checkbox is checked when it changes
+pub fn on_check<Msg: 'static>(message: fn(bool) -> Msg) -> Attribute<Msg> {
+    on("change", decode::target_checked().map(message))
+}
+
+/// Like `on_input`, but with a closure. The listener is only replaced when `key` changes, so
+/// it should hold the values the closure captures, like `on_input_with(id, move |value|
+/// Msg::Rename(id, value))`.
+pub fn on_input_with<Msg, K, F>(key: K, message: F) -> Attribute<Msg>
+where
+    Msg: 'static,
+    K: PartialEq + Debug + 'static,
+    F: Fn(String) -> Msg + 'static,
+{
     Attribute::Event {
         type_: "input".to_owned(),
-        to_message: EventToMessage::Input(message),
-        stop_propagation: true,
-        prevent_default: false,
-        js_closure: Default::default(),
+        to_message: EventToMessage::InputWithClosure(RcEventClosure::keyed(key, message)),
+        options: EventOptions {
+            stop_propagation: true,
+            ..Default::default()
+        },
+        listener: Default::default(),
     }
 }
 
-// TODO: Ensure that when we start using animationFrame, on_input gets special treatement
 pub fn on_input2<Msg: 'static + Debug, Data: Debug + Clone + PartialEq + 'static>(
     data: Data,
     message: fn(Data, String) -> Msg,
@@ -57,28 +67,19 @@ pub fn on_input2<Msg: 'static + Debug, Data: Debug + Clone + PartialEq + 'static
         to_message: EventToMessage::InputWithClosure(RcEventClosure(Rc::new(
             EventClosureImpl::new(data, message),
         ))),
-        stop_propagation: true,
-        prevent_default: false,
-        js_closure: Default::default(),
+        options: EventOptions {
+            stop_propagation: true,
+            ..Default::default()
+        },
+        listener: Default::default(),
     }
 }
 
-pub fn on_enter<Msg: Clone + 'static>(message: Msg) -> Attribute<Msg> {
-    Attribute::Event {
-        type_: "keydown".to_owned(),
-        to_message: EventToMessage::WithFilter {
-            msg: message,
-            filter: |event| {
-                let key_code = event
-                    .dyn_into::<web_sys::KeyboardEvent>()
-                    .ok()
-                    .map(|ev| ev.key_code())
-                    .unwrap_or(0);
-                key_code == 13
-            },
-        },
-        prevent_default: false,
-        stop_propagation: false,
-        js_closure: Default::default(),
-    }
+pub fn on_enter<Msg: PartialEq + Debug + Clone + 'static>(message: Msg) -> Attribute<Msg> {
+    on(
+        "keydown",
+        decode::key_code()
+            .filter(|code| *code == 13)
+            .map_to(message),
+    )
 }
@@ -1,12 +1,17 @@
-// TODO: Are we able to convert Html<A> to Html<B>?
-
-use std::any::Any;
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
 use std::cmp::PartialEq;
 use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr::fn_addr_eq;
 use std::rc::Rc;
 
-#[derive(Clone, Debug)]
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{decode::Decoder, dom_event::Event};
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct HtmlTag<Msg> {
     pub tag: String,
     pub attrs: Vec<Attribute<Msg>>,
@@ -24,7 +29,7 @@ impl<Msg> HtmlTag<Msg> {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Html<Msg> {
     Tag(HtmlTag<Msg>),
     Text(String),
@@ -41,7 +46,7 @@ impl<Msg> HtmlTag<Msg> {
             .iter()
             .map(|child| child.to_html_text(indent + 1))
             .collect::<Vec<_>>()
-            .join("\n");;
+            .join("\n");
         format!(
             "{}<{}>\n{}\n{}</{}>",
             indent_s, self.tag, children, indent_s, self.tag,
@@ -49,7 +54,61 @@ impl<Msg> HtmlTag<Msg> {
     }
 }
 
+impl<Msg: Debug + 'static> Html<Msg> {
+    /// Convert every message in this tree with `f`. This is used to embed the view of a module
+    /// with its own message type.
+    pub fn map<B, F>(self, f: F) -> Html<B>
+    where
+        B: Debug + 'static,
+        F: Fn(Msg) -> B + 'static,
+    {
+        self.map_with(&MsgMapper::new(f))
+    }
+
+    fn map_with<B: Debug + 'static>(self, mapper: &MsgMapper<Msg, B>) -> Html<B> {
+        match self {
+            Html::Text(text) => Html::Text(text),
+            Html::Tag(HtmlTag {
+                tag,
+                attrs,
+                children,
+            }) => Html::Tag(HtmlTag {
+                tag,
+                attrs: attrs
+                    .into_iter()
+                    .map(|attr| attr.map_with(mapper))
+                    .collect(),
+                children: children
+                    .into_iter()
+                    .map(|child| child.map_with(mapper))
+                    .collect(),
+            }),
+        }
+    }
+}
+
 impl<Msg> Html<Msg> {
+    /// Give every event in this tree a listener handle of its own. Clones of a node or of an
+    /// attribute share their handle, so the listener registered for one would replace the
+    /// other's. The render functions do this with every tree before it is mounted.
+    pub fn own_listeners(&mut self) {
+        if let Html::Tag(tag) = self {
+            for attr in &mut tag.attrs {
+                if let Attribute::Event { listener, .. } = attr {
+                    *listener = ListenerHandle::default();
+                }
+            }
+            for child in &mut tag.children {
+                child.own_listeners();
+            }
+        }
+    }
+
+    /// Serialise to HTML, see `ssr::render_to_string`
+    pub fn render_to_string(&self) -> String {
+        crate::ssr::render_to_string(self)
+    }
+
     pub fn to_html_text(&self, indent: u32) -> String {
         let indent_s = "  ".repeat(indent as usize);
         match self {
@@ -59,41 +118,113 @@ impl<Msg> Html<Msg> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PropertyValue {
     String(String),
     Bool(bool),
 }
 
+impl<'a> From<&'a str> for PropertyValue {
+    fn from(value: &'a str) -> Self {
+        PropertyValue::String(value.to_owned())
+    }
+}
+
+impl From<bool> for PropertyValue {
+    fn from(value: bool) -> Self {
+        PropertyValue::Bool(value)
+    }
+}
+
+/// The listener registered for an event attribute. What it holds depends on the backend it was
+/// rendered with, see `backend::Backend::Listener`.
 #[derive(Clone, Default)]
-pub struct JsClosure(pub Rc<RefCell<Option<wasm_bindgen::closure::Closure<Fn(web_sys::Event)>>>>);
+pub struct ListenerHandle(Rc<RefCell<Option<Box<Any>>>>);
 
-impl Debug for JsClosure {
+impl ListenerHandle {
+    pub fn set<L: 'static>(&self, listener: L) {
+        let ret = self.0.replace(Some(Box::new(listener)));
+
+        if ret.is_some() {
+            console_log!("ListenerHandle::set called, but it did already have a listener???");
+        }
+    }
+
+    pub fn take<L: 'static>(&self) -> Option<L> {
+        let listener = self.0.borrow_mut().take()?;
+        match listener.downcast() {
+            Ok(listener) => Some(*listener),
+            Err(_) => {
+                console_log!("WARN: The listener was registered by another backend");
+                None
+            }
+        }
+    }
+
+    pub fn get<L: Clone + 'static>(&self) -> Option<L> {
+        self.0
+            .borrow()
+            .as_ref()
+            .and_then(|listener| listener.downcast_ref::<L>())
+            .cloned()
+    }
+
+    /// Call `f` with the listener, if there is one of type `L`
+    pub fn with<L: 'static, R, F: FnOnce(&L) -> R>(&self, f: F) -> Option<R> {
+        self.0
+            .borrow()
+            .as_ref()
+            .and_then(|listener| listener.downcast_ref::<L>())
+            .map(f)
+    }
+
+    /// Move the listener to `other`, so that it is not dropped with this handle
+    pub fn move_to(&self, other: &ListenerHandle) {
+        if let Some(listener) = self.0.borrow_mut().take() {
+            other.0.replace(Some(listener));
+        }
+    }
+}
+
+impl Debug for ListenerHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.0.borrow().is_some() {
-            write!(f, "HAS A CLOSURE")
+            write!(f, "HAS A LISTENER")
         } else {
-            write!(f, "NO CLOSURE")
+            write!(f, "NO LISTENER")
         }
     }
 }
 
-impl PartialEq for JsClosure {
-    fn eq(&self, _: &JsClosure) -> bool {
-        // This is not good enough to implent Eq, i think
-        // And its a bit weird. But it's to ignore this in the Attribute enum
-        true
+impl PartialEq for ListenerHandle {
+    /// Handles are only equal to their clones, which share the listener
+    fn eq(&self, other: &ListenerHandle) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// How the listener of an event attribute is registered, and what is done with the events
+/// before their message is made. Set with the builder methods on `Attribute`, like
+/// `on_click(msg).prevent_default()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventOptions {
+    pub prevent_default: bool,
+    pub stop_propagation: bool,
+    /// Listen in the capture phase, before the event reaches its target
+    pub capture: bool,
+    /// Tell the browser that `preventDefault` is not called, so that it can scroll right away
+    pub passive: bool,
+    /// Stop listening after the first event
+    pub once: bool,
+}
+
+#[derive(Clone, Debug)]
 pub enum Attribute<Msg> {
     // Event where the message depends on the event data
     Event {
-        js_closure: JsClosure,
+        listener: ListenerHandle,
         type_: String,
-        stop_propagation: bool,
-        prevent_default: bool,
+        options: EventOptions,
         to_message: EventToMessage<Msg>,
     },
     // TODO: Value should be JsValue or something like that, not String
@@ -102,33 +233,131 @@ pub enum Attribute<Msg> {
     Key(String),
 }
 
+/// Events are equal when they make their messages the same way. Which listener is registered
+/// for them is left out, as the listener is handed over when they are equal, see `diff::diff`.
+impl<Msg: PartialEq> PartialEq for Attribute<Msg> {
+    fn eq(&self, other: &Attribute<Msg>) -> bool {
+        match (self, other) {
+            (
+                Attribute::Event {
+                    type_,
+                    options,
+                    to_message,
+                    ..
+                },
+                Attribute::Event {
+                    type_: other_type,
+                    options: other_options,
+                    to_message: other_to_message,
+                    ..
+                },
+            ) => type_ == other_type && options == other_options && to_message == other_to_message,
+            (Attribute::Property(key, value), Attribute::Property(other_key, other_value)) => {
+                key == other_key && value == other_value
+            }
+            (Attribute::Style(property, value), Attribute::Style(other_property, other_value)) => {
+                property == other_property && value == other_value
+            }
+            (Attribute::Key(key), Attribute::Key(other_key)) => key == other_key,
+            _ => false,
+        }
+    }
+}
+
+impl<Msg: Debug + 'static> Attribute<Msg> {
+    /// Convert the message of this attribute with `f`, see `Html::map`
+    pub fn map<B, F>(self, f: F) -> Attribute<B>
+    where
+        B: Debug + 'static,
+        F: Fn(Msg) -> B + 'static,
+    {
+        self.map_with(&MsgMapper::new(f))
+    }
+
+    fn map_with<B: Debug + 'static>(self, mapper: &MsgMapper<Msg, B>) -> Attribute<B> {
+        match self {
+            Attribute::Event {
+                type_,
+                options,
+                to_message,
+                ..
+            } => Attribute::Event {
+                // The handle belongs to the listener of the unmapped attribute, if any
+                listener: Default::default(),
+                type_,
+                options,
+                to_message: to_message.map_with(mapper),
+            },
+            Attribute::Property(key, value) => Attribute::Property(key, value),
+            Attribute::Style(property, value) => Attribute::Style(property, value),
+            Attribute::Key(key) => Attribute::Key(key),
+        }
+    }
+}
+
 impl<Msg> Attribute<Msg> {
     pub fn is_event(&self) -> bool {
-        match self {
-            Attribute::Event { .. } => true,
+        matches!(self, Attribute::Event { .. })
+    }
+
+    /// Whether both are events that one listener can handle, which is when they only differ in
+    /// how the message is made
+    pub fn shares_listener(&self, other: &Attribute<Msg>) -> bool {
+        match (self, other) {
+            (
+                Attribute::Event { type_, options, .. },
+                Attribute::Event {
+                    type_: other_type,
+                    options: other_options,
+                    ..
+                },
+            ) => type_ == other_type && options == other_options,
             _ => false,
         }
     }
 
-    /// Panics if self is not an event
-    pub fn get_js_closure(&self) -> JsClosure {
-        match self {
-            Attribute::Event { js_closure, .. } => js_closure.clone(),
-            _ => panic!("get_js_closure called with something that is not an event"),
+    /// Call `preventDefault` on the events, for example to keep a link from navigating. This
+    /// and the other options do nothing for attributes that are not events.
+    pub fn prevent_default(self) -> Self {
+        self.update_options(|options| options.prevent_default = true)
+    }
+
+    /// Keep the events from reaching the listeners of the ancestors
+    pub fn stop_propagation(self) -> Self {
+        self.update_options(|options| options.stop_propagation = true)
+    }
+
+    pub fn capture(self) -> Self {
+        self.update_options(|options| options.capture = true)
+    }
+
+    pub fn passive(self) -> Self {
+        self.update_options(|options| options.passive = true)
+    }
+
+    /// Only get the first event. The listener is kept as long as the view has the same event,
+    /// so the element gets one message in total.
+    pub fn once(self) -> Self {
+        self.update_options(|options| options.once = true)
+    }
+
+    /// Replace all the options of an event
+    pub fn with_options(self, options: EventOptions) -> Self {
+        self.update_options(|current| *current = options)
+    }
+
+    fn update_options<F: FnOnce(&mut EventOptions)>(mut self, f: F) -> Self {
+        if let Attribute::Event { options, .. } = &mut self {
+            f(options);
         }
+        self
     }
 
-    /// Panics if self is not an event
-    pub fn set_js_closure(&self, closure: wasm_bindgen::closure::Closure<Fn(web_sys::Event)>) {
+    /// The listener of an event, `None` for other attributes
+    pub fn listener(&self) -> Option<&ListenerHandle> {
         match self {
-            Attribute::Event { js_closure, .. } => {
-                let ret = js_closure.0.replace(Some(closure));
-
-                if ret.is_some() {
-                    console_log!("set_js_closure called, but event did already have a closure???");
-                }
-            }
-            _ => panic!("set_js_closure called with something that is not an event"),
+            Attribute::Event { listener, .. } => Some(listener),
+            _ => None,
         }
     }
 }
@@ -136,6 +365,7 @@ impl<Msg> Attribute<Msg> {
 pub trait EventClosure<Input, Msg>: Debug {
     fn call_ish(&self, input: Input) -> Msg;
     fn eq_rc(&self, other: &Rc<EventClosure<Input, Msg>>) -> bool;
+    fn as_any(&self) -> &Any;
 }
 
 #[derive(Debug)]
@@ -158,14 +388,166 @@ impl<Input: Debug + 'static, Data: PartialEq + Debug + Clone + 'static, Msg: Deb
     }
 
     fn eq_rc(&self, other: &Rc<EventClosure<Input, Msg>>) -> bool {
-        let other = other as &Any;
+        if let Some(other_down) = other
+            .as_any()
+            .downcast_ref::<EventClosureImpl<Input, Data, Msg>>()
+        {
+            self.data == other_down.data && fn_addr_eq(self.func, other_down.func)
+        } else {
+            false
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// An `EventClosure` made from any closure. Closures can not be compared, so `key` stands in
+/// for what the closure captured: two `KeyedClosure`s are equal if they were made by the same
+/// closure expression with equal keys. Put every captured value that changes the message in the
+/// key, like `(id, name.clone())`, or the old closure is kept when the view changes.
+pub struct KeyedClosure<Input, Key, F> {
+    key: Key,
+    func: F,
+    input: PhantomData<fn(Input)>,
+}
+
+impl<Input, Key, F> KeyedClosure<Input, Key, F> {
+    pub fn new(key: Key, func: F) -> Self {
+        KeyedClosure {
+            key,
+            func,
+            input: PhantomData,
+        }
+    }
+}
+
+impl<Input, Key: Debug, F> Debug for KeyedClosure<Input, Key, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "KeyedClosure({:?})", self.key)
+    }
+}
+
+impl<Input, Key, F, Msg> EventClosure<Input, Msg> for KeyedClosure<Input, Key, F>
+where
+    Input: 'static,
+    Key: PartialEq + Debug + 'static,
+    F: Fn(Input) -> Msg + 'static,
+{
+    fn call_ish(&self, input: Input) -> Msg {
+        (self.func)(input)
+    }
+
+    // Every closure expression has its own type, so the downcast only succeeds for the same one
+    fn eq_rc(&self, other: &Rc<EventClosure<Input, Msg>>) -> bool {
+        if let Some(other_down) = other.as_any().downcast_ref::<KeyedClosure<Input, Key, F>>() {
+            self.key == other_down.key
+        } else {
+            false
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+impl<Input, Msg> RcEventClosure<Input, Msg> {
+    /// Share `func` as an `EventClosure`, compared by `key`, see `KeyedClosure`
+    pub fn keyed<Key, F>(key: Key, func: F) -> Self
+    where
+        Input: 'static,
+        Key: PartialEq + Debug + 'static,
+        F: Fn(Input) -> Msg + 'static,
+    {
+        RcEventClosure(Rc::new(KeyedClosure::new(key, func)))
+    }
+}
+
+/// A function converting messages of type `A` to `B`, shared by every attribute in a mapped
+/// tree. Closures can not be compared, but a closure that does not capture anything always
+/// does the same thing, so two mappers are equal if they are the same capture-free closure
+/// (or function). This keeps mapped listeners from being re-registered on every render.
+pub struct MsgMapper<A, B> {
+    func: Rc<Fn(A) -> B>,
+    type_id: TypeId,
+    captures: bool,
+}
+
+impl<A, B> MsgMapper<A, B> {
+    pub fn new<F>(func: F) -> Self
+    where
+        F: Fn(A) -> B + 'static,
+    {
+        MsgMapper {
+            func: Rc::new(func),
+            type_id: TypeId::of::<F>(),
+            captures: mem::size_of::<F>() != 0,
+        }
+    }
+
+    pub fn call(&self, msg: A) -> B {
+        (self.func)(msg)
+    }
+}
+
+impl<A, B> Clone for MsgMapper<A, B> {
+    fn clone(&self) -> Self {
+        MsgMapper {
+            func: self.func.clone(),
+            type_id: self.type_id,
+            captures: self.captures,
+        }
+    }
+}
+
+impl<A, B> Debug for MsgMapper<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MsgMapper")
+    }
+}
+
+impl<A, B> PartialEq for MsgMapper<A, B> {
+    fn eq(&self, other: &MsgMapper<A, B>) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+            || (!self.captures && !other.captures && self.type_id == other.type_id)
+    }
+}
+
+/// An `EventClosure` whose message is converted with a `MsgMapper`
+pub struct MappedEventClosure<Input, A, B> {
+    inner: Rc<EventClosure<Input, A>>,
+    mapper: MsgMapper<A, B>,
+}
+
+impl<Input, A, B> Debug for MappedEventClosure<Input, A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MappedEventClosure({:?})", self.inner)
+    }
+}
+
+impl<Input: 'static, A: 'static, B: 'static> EventClosure<Input, B>
+    for MappedEventClosure<Input, A, B>
+{
+    fn call_ish(&self, input: Input) -> B {
+        self.mapper.call(self.inner.call_ish(input))
+    }
 
-        if let Some(other_down) = other.downcast_ref::<EventClosureImpl<Input, Data, Msg>>() {
-            self.data == other_down.data && self.func == other_down.func
+    fn eq_rc(&self, other: &Rc<EventClosure<Input, B>>) -> bool {
+        if let Some(other_down) = other
+            .as_any()
+            .downcast_ref::<MappedEventClosure<Input, A, B>>()
+        {
+            self.inner.eq_rc(&other_down.inner) && self.mapper == other_down.mapper
         } else {
             false
         }
     }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -177,15 +559,46 @@ impl<Input, Msg> PartialEq for RcEventClosure<Input, Msg> {
     }
 }
 
+/// How the message for an event is made
 #[derive(Clone, Debug, PartialEq)]
 pub enum EventToMessage<Msg> {
-    StaticMsg(Msg),
-    Input(fn(String) -> Msg),
+    Decoder(Decoder<Msg>),
     InputWithClosure(RcEventClosure<String, Msg>),
-    WithFilter {
-        msg: Msg,
-        filter: fn(web_sys::Event) -> bool,
-    },
+}
+
+impl<Msg: Clone> EventToMessage<Msg> {
+    /// The message to dispatch for `event`, if any
+    pub fn to_message(&self, event: &Event) -> Option<Msg> {
+        match self {
+            EventToMessage::Decoder(decoder) => decoder.decode(event).ok(),
+            EventToMessage::InputWithClosure(closure) => {
+                Some(closure.0.call_ish(event.target_value()))
+            }
+        }
+    }
+
+    /// Input events are rendered right away, so that the value of the input element is in sync
+    /// with the model before the browser continues
+    pub fn is_input(&self) -> bool {
+        match self {
+            EventToMessage::Decoder(decoder) => decoder.reads_target(),
+            EventToMessage::InputWithClosure(_) => true,
+        }
+    }
+}
+
+impl<Msg: Debug + 'static> EventToMessage<Msg> {
+    fn map_with<B: Debug + 'static>(self, mapper: &MsgMapper<Msg, B>) -> EventToMessage<B> {
+        match self {
+            EventToMessage::Decoder(decoder) => EventToMessage::Decoder(decoder.map_with(mapper)),
+            EventToMessage::InputWithClosure(closure) => {
+                EventToMessage::InputWithClosure(RcEventClosure(Rc::new(MappedEventClosure {
+                    inner: closure.0,
+                    mapper: mapper.clone(),
+                })))
+            }
+        }
+    }
 }
 
 macro_rules! create_node {
@@ -221,3 +634,100 @@ create_node!(p);
 pub fn text<Msg>(inner: &str) -> Html<Msg> {
     Html::Text(inner.to_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom_event::EventData;
+    use crate::events::{on_click, on_input};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Inner {
+        Click,
+        Input(String),
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Outer {
+        Inner(Inner),
+    }
+
+    fn inner_view() -> Html<Inner> {
+        div(
+            &[on_click(Inner::Click)],
+            &[input(&[on_input(Inner::Input)], &[])],
+        )
+    }
+
+    #[test]
+    fn sets_event_options() {
+        match on_click(Inner::Click).prevent_default().once() {
+            Attribute::Event { options, .. } => assert_eq!(
+                options,
+                EventOptions {
+                    prevent_default: true,
+                    once: true,
+                    ..Default::default()
+                }
+            ),
+            attr => panic!("unexpected attribute {:?}", attr),
+        }
+        assert_ne!(
+            on_click(Inner::Click).capture(),
+            on_click(Inner::Click).passive()
+        );
+    }
+
+    #[test]
+    fn map_converts_messages() {
+        let mapped = inner_view().map(Outer::Inner);
+
+        let (div, input) = match &mapped {
+            Html::Tag(div) => match &div.children[0] {
+                Html::Tag(input) => (div, input),
+                _ => panic!("expected an input"),
+            },
+            _ => panic!("expected a div"),
+        };
+        let event = Event::from_data(EventData {
+            value: "text".to_owned(),
+            ..Default::default()
+        });
+        match &div.attrs[0] {
+            Attribute::Event { to_message, .. } => assert_eq!(
+                to_message.to_message(&event),
+                Some(Outer::Inner(Inner::Click))
+            ),
+            attr => panic!("unexpected attribute {:?}", attr),
+        }
+        match &input.attrs[0] {
+            Attribute::Event { to_message, .. } => assert_eq!(
+                to_message.to_message(&event),
+                Some(Outer::Inner(Inner::Input("text".to_owned())))
+            ),
+            attr => panic!("unexpected attribute {:?}", attr),
+        }
+    }
+
+    #[test]
+    fn mapped_attributes_are_equal_between_renders() {
+        let first = inner_view().map(Outer::Inner);
+        let second = inner_view().map(Outer::Inner);
+
+        let attrs = |html: &Html<Outer>| match html {
+            Html::Tag(div) => match &div.children[0] {
+                Html::Tag(input) => (div.attrs.clone(), input.attrs.clone()),
+                _ => panic!("expected an input"),
+            },
+            _ => panic!("expected a div"),
+        };
+        assert_eq!(attrs(&first), attrs(&second));
+
+        let prefix = "captured".to_owned();
+        let capturing = inner_view().map(move |msg| {
+            let _ = &prefix;
+            Outer::Inner(msg)
+        });
+        assert_ne!(attrs(&first).1, attrs(&capturing).1);
+    }
+}
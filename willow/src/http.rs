@@ -0,0 +1,506 @@
+//! HTTP requests as commands.
+//!
+//! A request is built with `Request::get` or `Request::post`, and turned into a command with
+//! one of the `expect_*` methods, which decode the response body into a message. Requests are
+//! sent through a `Transport`, which is `fetch` in the browser. Tests plug in a `FakeServer`
+//! with `set_transport` instead, and check the requests it got.
+
+use std::cell::RefCell;
+use std::fmt::{self, Debug};
+use std::ptr::fn_addr_eq;
+use std::rc::Rc;
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    cmd::{Dispatcher, Effect},
+    task::{self, Task},
+    Cmd, Error,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl Method {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    /// Give up with an error if there is no response in time
+    pub timeout: Option<Duration>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: &str) -> Self {
+        Request {
+            method,
+            url: url.to_owned(),
+            headers: vec![],
+            body: None,
+            timeout: None,
+        }
+    }
+
+    pub fn get(url: &str) -> Self {
+        Request::new(Method::Get, url)
+    }
+
+    pub fn post(url: &str) -> Self {
+        Request::new(Method::Post, url)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = Some(body.to_owned());
+        self
+    }
+
+    /// Send `value` as JSON, with the matching `Content-Type`
+    pub fn json<T: Serialize>(self, value: &T) -> Result<Self, Error> {
+        let body = serde_json::to_string(value).map_err(|err| Error::from(err.to_string()))?;
+        Ok(self.header("Content-Type", "application/json").body(&body))
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send the request with the current transport. Responses with a status outside of 2xx are
+    /// not errors here, see `Response::error_for_status`.
+    pub fn send(self) -> Task<Response, Error> {
+        Task::new(async move { transport()?.send(&self).await })
+    }
+
+    /// A command that sends the request, and dispatches the message for the body decoded with
+    /// `decode`. Responses with a status outside of 2xx give an error instead.
+    pub fn expect<T, Msg>(
+        self,
+        decode: fn(&str) -> Result<T, Error>,
+        to_message: fn(Result<T, Error>) -> Msg,
+    ) -> Cmd<Msg>
+    where
+        T: 'static,
+        Msg: Clone + 'static,
+    {
+        Cmd::effect(Expect {
+            request: self,
+            decode,
+            to_message,
+        })
+    }
+
+    /// Decode the response body as JSON
+    pub fn expect_json<T, Msg>(self, to_message: fn(Result<T, Error>) -> Msg) -> Cmd<Msg>
+    where
+        T: DeserializeOwned + 'static,
+        Msg: Clone + 'static,
+    {
+        self.expect(decode_json, to_message)
+    }
+
+    pub fn expect_string<Msg>(self, to_message: fn(Result<String, Error>) -> Msg) -> Cmd<Msg>
+    where
+        Msg: Clone + 'static,
+    {
+        self.expect(|body| Ok(body.to_owned()), to_message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, body: &str) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body: body.to_owned(),
+        }
+    }
+
+    /// A response with `value` as JSON, with the matching `Content-Type`
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Result<Self, Error> {
+        let body = serde_json::to_string(value).map_err(|err| Error::from(err.to_string()))?;
+        Ok(Response::new(status, &body).header("Content-Type", "application/json"))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn error_for_status(self) -> Result<Self, Error> {
+        if self.status >= 200 && self.status < 300 {
+            Ok(self)
+        } else {
+            Err(Error::from(format!("Got status {}", self.status)))
+        }
+    }
+}
+
+fn decode_json<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|err| Error::from(err.to_string()))
+}
+
+/// Sends requests somewhere
+pub trait Transport {
+    fn send(&self, request: &Request) -> Task<Response, Error>;
+}
+
+thread_local! {
+    static TRANSPORT: RefCell<Option<Rc<Transport>>> = RefCell::new(None);
+}
+
+/// Send the requests of this thread through `transport`
+pub fn set_transport<T: Transport + 'static>(transport: Rc<T>) {
+    TRANSPORT.with(|current| current.replace(Some(transport)));
+}
+
+fn transport() -> Result<Rc<Transport>, Error> {
+    let transport = TRANSPORT.with(|current| current.borrow().clone());
+    match transport {
+        Some(transport) => Ok(transport),
+        #[cfg(feature = "web")]
+        None => Ok(Rc::new(fetch::Fetch)),
+        #[cfg(not(feature = "web"))]
+        None => Err(Error::from("No HTTP transport, see `http::set_transport`")),
+    }
+}
+
+/// The command made by `Request::expect`
+pub struct Expect<T, Msg> {
+    request: Request,
+    decode: fn(&str) -> Result<T, Error>,
+    to_message: fn(Result<T, Error>) -> Msg,
+}
+
+impl<T: 'static, Msg: 'static> Effect<Msg> for Expect<T, Msg> {
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error> {
+        let response = transport()?.send(&self.request);
+        let decode = self.decode;
+        let to_message = self.to_message;
+        let dispatcher = dispatcher.clone();
+
+        task::spawn_local(async move {
+            let result = response
+                .await
+                .and_then(Response::error_for_status)
+                .and_then(|response| decode(&response.body));
+            dispatcher.dispatch(to_message(result));
+        });
+        Ok(())
+    }
+}
+
+impl<T, Msg> PartialEq for Expect<T, Msg> {
+    fn eq(&self, other: &Self) -> bool {
+        self.request == other.request
+            && fn_addr_eq(self.decode, other.decode)
+            && fn_addr_eq(self.to_message, other.to_message)
+    }
+}
+
+impl<T, Msg> Debug for Expect<T, Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Expect").field(&self.request).finish()
+    }
+}
+
+/// A server in memory, to test commands that send requests. It answers with the responses it
+/// is given, and 404 to everything else.
+#[derive(Default)]
+pub struct FakeServer {
+    routes: RefCell<Vec<(Method, String, Response)>>,
+    requests: RefCell<Vec<Request>>,
+}
+
+impl FakeServer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Answer requests to `url` with `response`
+    pub fn respond(&self, method: Method, url: &str, response: Response) {
+        self.routes
+            .borrow_mut()
+            .push((method, url.to_owned(), response));
+    }
+
+    /// Every request sent here, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.borrow().clone()
+    }
+}
+
+impl Transport for FakeServer {
+    fn send(&self, request: &Request) -> Task<Response, Error> {
+        self.requests.borrow_mut().push(request.clone());
+
+        let response = self
+            .routes
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(method, url, _)| *method == request.method && *url == request.url)
+            .map(|(_, _, response)| response.clone())
+            .unwrap_or_else(|| Response::new(404, "Not Found"));
+        Task::succeed(response)
+    }
+}
+
+#[cfg(feature = "web")]
+pub use self::fetch::Fetch;
+
+#[cfg(feature = "web")]
+mod fetch {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use js_sys::{Array, Function};
+    use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{AbortController, Headers, RequestInit};
+
+    use super::{Request, Response, Transport};
+    use crate::{task::Task, Error};
+
+    /// Sends requests with `window.fetch`
+    pub struct Fetch;
+
+    impl Transport for Fetch {
+        fn send(&self, request: &Request) -> Task<Response, Error> {
+            Task::new(fetch(request.clone()))
+        }
+    }
+
+    async fn fetch(request: Request) -> Result<Response, Error> {
+        let window = web_sys::window().ok_or_else(|| Error::from("no global `window` exists"))?;
+
+        let headers = Headers::new()?;
+        for (name, value) in &request.headers {
+            headers.append(name, value)?;
+        }
+        let controller = AbortController::new()?;
+
+        let init = RequestInit::new();
+        init.set_method(request.method.as_str());
+        init.set_headers(&headers);
+        init.set_signal(Some(&controller.signal()));
+        if let Some(body) = &request.body {
+            init.set_body(&JsValue::from_str(body));
+        }
+
+        // Abort the request when it times out. The closure has to live until then.
+        let timed_out = Rc::new(Cell::new(false));
+        let mut timer = None;
+        if let Some(timeout) = request.timeout {
+            let timed_out = timed_out.clone();
+            let on_timeout = Closure::once(move || {
+                timed_out.set(true);
+                controller.abort();
+            });
+            let handle = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                on_timeout.as_ref().unchecked_ref::<Function>(),
+                timeout.as_millis() as i32,
+            )?;
+            timer = Some((handle, on_timeout));
+        }
+
+        let result = send(&window, &request.url, &init).await;
+        if let Some((handle, _)) = timer {
+            window.clear_timeout_with_handle(handle);
+        }
+
+        match result {
+            Err(_) if timed_out.get() => Err(Error::from(format!(
+                "The request to {} timed out",
+                request.url
+            ))),
+            result => result,
+        }
+    }
+
+    async fn send(
+        window: &web_sys::Window,
+        url: &str,
+        init: &RequestInit,
+    ) -> Result<Response, Error> {
+        let response: web_sys::Response = JsFuture::from(window.fetch_with_str_and_init(url, init))
+            .await?
+            .dyn_into()?;
+
+        let mut headers = vec![];
+        if let Some(entries) = js_sys::try_iter(&response.headers())? {
+            for entry in entries {
+                let entry: Array = entry?.dyn_into()?;
+                headers.push((
+                    entry.get(0).as_string().unwrap_or_default(),
+                    entry.get(1).as_string().unwrap_or_default(),
+                ));
+            }
+        }
+        let body = JsFuture::from(response.text()?).await?;
+
+        Ok(Response {
+            status: response.status(),
+            headers,
+            body: body.as_string().unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use super::{set_transport, FakeServer, Method, Request, Response};
+    use crate::html::{div, text, Html};
+    use crate::testing::TestProgram;
+    use crate::{Cmd, Error, Program, Sub};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Todo {
+        title: String,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Load,
+        Add(String),
+        Loaded(Result<Vec<Todo>, Error>),
+        Added(Result<String, Error>),
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Model {
+        todos: Vec<Todo>,
+        error: Option<String>,
+    }
+
+    fn update(msg: &Msg, model: &mut Model) -> Cmd<Msg> {
+        match msg {
+            Msg::Load => Request::get("/todos")
+                .header("Accept", "application/json")
+                .timeout(Duration::from_secs(5))
+                .expect_json(Msg::Loaded),
+            Msg::Add(title) => Request::post("/todos")
+                .json(&Todo {
+                    title: title.clone(),
+                })
+                .unwrap()
+                .expect_string(Msg::Added),
+            Msg::Loaded(Ok(todos)) => {
+                model.todos = todos.clone();
+                Cmd::none()
+            }
+            Msg::Loaded(Err(err)) | Msg::Added(Err(err)) => {
+                model.error = Some(err.to_string());
+                Cmd::none()
+            }
+            Msg::Added(Ok(_)) => Cmd::none(),
+        }
+    }
+
+    fn view(_: &Model) -> Html<Msg> {
+        div(&[], &[text("todos")])
+    }
+
+    #[test]
+    fn sends_requests_through_the_transport() {
+        let server = Rc::new(FakeServer::new());
+        server.respond(
+            Method::Get,
+            "/todos",
+            Response::json(
+                200,
+                &vec![Todo {
+                    title: "Buy milk".to_owned(),
+                }],
+            )
+            .unwrap(),
+        );
+        set_transport(server.clone());
+
+        let mut app = TestProgram::new(Program::new(
+            view,
+            update,
+            |_| Sub::none(),
+            Model::default(),
+        ));
+        app.dispatch(Msg::Load);
+        assert_eq!(
+            app.cmds(),
+            &[Request::get("/todos")
+                .header("Accept", "application/json")
+                .timeout(Duration::from_secs(5))
+                .expect_json(Msg::Loaded)]
+        );
+
+        app.run_cmds().unwrap();
+        assert_eq!(app.model().todos[0].title, "Buy milk");
+
+        app.dispatch(Msg::Add("Walk the dog".to_owned()));
+        app.run_cmds().unwrap();
+        assert_eq!(app.model().error, Some("Got status 404".to_owned()));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, Method::Post);
+        assert_eq!(
+            requests[1].body,
+            Some(r#"{"title":"Walk the dog"}"#.to_owned())
+        );
+    }
+    #[test]
+    fn bodies_that_can_not_be_decoded_are_errors() {
+        let server = Rc::new(FakeServer::new());
+        server.respond(
+            Method::Get,
+            "/todos",
+            Response::new(200, r#"[{"title": "#).header("Content-Type", "application/json"),
+        );
+        set_transport(server);
+
+        let mut app = TestProgram::new(Program::new(
+            view,
+            update,
+            |_| Sub::none(),
+            Model::default(),
+        ));
+        app.dispatch(Msg::Load);
+        app.run_cmds().unwrap();
+
+        assert!(app.model().todos.is_empty());
+        assert!(app.model().error.is_some());
+    }
+}
@@ -1,15 +1,31 @@
-#![feature(arbitrary_self_types)]
-
 #[macro_use]
 pub mod utils;
 pub mod attributes;
+pub mod backend;
 pub mod cmd;
+pub mod decode;
+pub mod delegate;
+pub mod diff;
+pub mod dom_event;
+mod error;
 pub mod events;
 pub mod html;
+pub mod http;
 mod program;
-mod render;
+pub mod query;
+pub mod remote;
+pub mod render;
+pub mod ssr;
+pub mod storage;
+pub mod sub;
+pub mod task;
+pub mod testing;
 
-pub use self::cmd::Cmd;
+pub use self::cmd::{Cmd, Dispatcher};
+pub use self::error::Error;
+#[cfg(feature = "web")]
+pub use self::program::Mount;
+pub use self::sub::Sub;
 pub use self::{program::Program, utils::log};
 
 #[cfg(test)]
@@ -1,16 +1,109 @@
+#[cfg(feature = "web")]
+use std::cell::Cell;
 use std::cell::RefCell;
 use std::fmt::Debug;
 use std::rc::Rc;
 
-use web_sys;
+#[cfg(feature = "web")]
+use wasm_bindgen::prelude::*;
+#[cfg(feature = "web")]
+use wasm_bindgen::JsCast;
+#[cfg(feature = "web")]
+use web_sys::{self, Node};
 
-use crate::{html::Html, render, Cmd};
+#[cfg(feature = "web")]
+use crate::{
+    backend::web::WebBackend,
+    delegate::{self, Delegation},
+    dom_event::Event,
+    render,
+    sub::Subscriptions,
+};
+use crate::{
+    cmd::Dispatcher,
+    html::Html,
+    remote::{RemoteEvent, RemotePatch, RemoteView},
+    sub::Sub,
+    utils, Cmd,
+};
+
+/// Where a program renders its view
+#[cfg(feature = "web")]
+#[derive(Clone, Debug)]
+pub enum Mount {
+    // The element with the given id
+    Id(String),
+    Element(web_sys::Element),
+    Body,
+}
+
+#[cfg(feature = "web")]
+impl Mount {
+    fn to_node(&self) -> Result<Node, JsValue> {
+        let window =
+            web_sys::window().ok_or_else(|| JsValue::from_str("no global `window` exists"))?;
+        let document = window
+            .document()
+            .ok_or_else(|| JsValue::from_str("should have a document on window"))?;
+
+        match self {
+            Mount::Id(id) => document
+                .get_element_by_id(id)
+                .map(Into::into)
+                .ok_or_else(|| {
+                    JsValue::from_str(&format!("did not find an element with id {}", id))
+                }),
+            Mount::Element(element) => Ok(element.clone().into()),
+            Mount::Body => document
+                .body()
+                .map(Into::into)
+                .ok_or_else(|| JsValue::from_str("document does not have a body")),
+        }
+    }
+}
+
+#[cfg(feature = "web")]
+impl<'a> From<&'a str> for Mount {
+    fn from(id: &'a str) -> Self {
+        Mount::Id(id.to_owned())
+    }
+}
+
+#[cfg(feature = "web")]
+impl From<web_sys::Element> for Mount {
+    fn from(element: web_sys::Element) -> Self {
+        Mount::Element(element)
+    }
+}
 
 pub struct Program<Model, Msg> {
     pub view: Box<Fn(&Model) -> Html<Msg>>,
-    pub update: Box<Fn(&Msg, &mut Model) -> Box<Cmd<Msg>>>,
+    pub update: Box<Fn(&Msg, &mut Model) -> Cmd<Msg>>,
+    pub subscriptions: Box<Fn(&Model) -> Sub<Msg>>,
     pub current_model: RefCell<Model>,
     pub last_tree: RefCell<Option<Html<Msg>>>,
+    /// Run when the program starts, see `with_init_cmd`
+    pub init_cmd: RefCell<Cmd<Msg>>,
+    // Where the patches go when the program is started with `start_remote`
+    remote: RefCell<Option<RemoteView<Msg>>>,
+    // The node the view is rendered into, set by `start`
+    #[cfg(feature = "web")]
+    root: RefCell<Option<Node>>,
+    // Set by `with_delegation`, the listeners on the root are added by `start`
+    #[cfg(feature = "web")]
+    delegate: Cell<bool>,
+    #[cfg(feature = "web")]
+    delegation: RefCell<Option<Delegation<WebBackend>>>,
+    #[cfg(feature = "web")]
+    active_subscriptions: RefCell<Subscriptions<Msg>>,
+    // The model has changed since the last render
+    #[cfg(feature = "web")]
+    dirty: Cell<bool>,
+    // An animation frame is requested, and will render if the program is still dirty
+    #[cfg(feature = "web")]
+    frame_requested: Cell<bool>,
+    #[cfg(feature = "web")]
+    on_animation_frame: RefCell<Option<Closure<Fn()>>>,
 }
 
 impl<Model, Msg> Program<Model, Msg>
@@ -18,63 +111,494 @@ where
     Model: Debug + Clone + 'static,
     Msg: PartialEq + Debug + Clone + 'static,
 {
-    pub fn new<ViewFn, UpdateFn>(view: ViewFn, update: UpdateFn, initial: Model) -> Self
+    pub fn new<ViewFn, UpdateFn, SubscriptionsFn>(
+        view: ViewFn,
+        update: UpdateFn,
+        subscriptions: SubscriptionsFn,
+        initial: Model,
+    ) -> Self
     where
         ViewFn: Fn(&Model) -> Html<Msg> + 'static,
-        UpdateFn: Fn(&Msg, &mut Model) -> Box<Cmd<Msg>> + 'static,
+        UpdateFn: Fn(&Msg, &mut Model) -> Cmd<Msg> + 'static,
+        SubscriptionsFn: Fn(&Model) -> Sub<Msg> + 'static,
     {
         Self {
             view: Box::new(view),
             update: Box::new(update),
+            subscriptions: Box::new(subscriptions),
             current_model: RefCell::new(initial),
             last_tree: RefCell::new(None),
+            init_cmd: RefCell::new(Cmd::None),
+            remote: RefCell::new(None),
+            #[cfg(feature = "web")]
+            root: RefCell::new(None),
+            #[cfg(feature = "web")]
+            delegate: Cell::new(false),
+            #[cfg(feature = "web")]
+            delegation: RefCell::new(None),
+            #[cfg(feature = "web")]
+            active_subscriptions: RefCell::new(Subscriptions::new()),
+            #[cfg(feature = "web")]
+            dirty: Cell::new(false),
+            #[cfg(feature = "web")]
+            frame_requested: Cell::new(false),
+            #[cfg(feature = "web")]
+            on_animation_frame: RefCell::new(None),
         }
     }
 
+    /// Run `cmd` when the program starts, for example to load what the initial model needs
+    pub fn with_init_cmd(self, cmd: Cmd<Msg>) -> Self {
+        self.init_cmd.replace(cmd);
+        self
+    }
+
+    /// Listen for events with one listener per event type on the mount node, instead of one per
+    /// handler. This saves a closure and a listener for every handler in the view, which adds up
+    /// for long lists. See `delegate`.
+    #[cfg(feature = "web")]
+    pub fn with_delegation(self) -> Self {
+        self.delegate.set(true);
+        self
+    }
+
+    /// Update the model with `message`. The view is rendered on the next animation frame, so
+    /// messages that arrive in the same frame are only rendered once.
     pub fn dispatch(self: &Rc<Self>, message: &Msg) {
+        self.process(message, false);
+    }
+
+    /// Like `dispatch`, but renders right away. This is used for controlled inputs, where the
+    /// DOM has to be updated before the browser continues, or the cursor will jump around.
+    pub fn dispatch_immediately(self: &Rc<Self>, message: &Msg) {
+        self.process(message, true);
+    }
+
+    fn process(self: &Rc<Self>, message: &Msg, render_immediately: bool) {
         let mut model = self.current_model.borrow().clone();
 
-        let window = web_sys::window().expect("no global `window` exists");
-        let performance = window
-            .performance()
-            .expect("should have performance on window");
-        let start_time = performance.now();
+        let start_time = utils::now();
         let cmd = (self.update)(message, &mut model);
-        let end_time = performance.now();
+        let end_time = utils::now();
         console_log!("Update took {} ms", end_time - start_time);
 
         // console_log!("Model: {:?}", model);
 
         self.current_model.replace(model);
 
-        self.render();
+        if render_immediately {
+            self.render();
+        } else {
+            self.schedule_render();
+        }
+        self.update_subscriptions();
+        self.run_cmd(&cmd);
+    }
 
-        if let Err(err) = cmd.run() {
+    fn run_cmd(self: &Rc<Self>, cmd: &Cmd<Msg>) {
+        if let Err(err) = cmd.run(&self.dispatcher()) {
             console_log!("Got error running cmd: {:?}", err);
         }
     }
 
+    fn run_init_cmd(self: &Rc<Self>) {
+        let cmd = self.init_cmd.replace(Cmd::None);
+        self.run_cmd(&cmd);
+    }
+
+    /// A dispatcher that sends messages to this program
+    pub fn dispatcher(self: &Rc<Self>) -> Dispatcher<Msg> {
+        let program = self.clone();
+        let immediate = self.clone();
+        Dispatcher::new(move |message| program.dispatch(&message))
+            .with_immediate(move |message| immediate.dispatch_immediately(&message))
+    }
+
     pub fn render(self: &Rc<Self>) {
-        let tree = (self.view)(&self.current_model.borrow());
+        if self.remote.borrow().is_some() {
+            self.render_remote();
+        } else {
+            self.render_view(false);
+        }
+    }
 
-        // console_log!("New view: {}", tree.to_html_text(0));
+    /// Run the program without a DOM, for example in a Web Worker. The patches of every render
+    /// are given to `send`, to be applied by a `remote::RemoteApplier` where the DOM is, and
+    /// events come back through `dispatch_remote`. Subscriptions that need a window, and
+    /// rendering inputs right away, are not available this way.
+    pub fn start_remote<F>(self: &Rc<Self>, send: F)
+    where
+        F: Fn(Vec<RemotePatch>) + 'static,
+    {
+        self.remote.replace(Some(RemoteView::new(send)));
+
+        self.render();
+        self.update_subscriptions();
+        self.run_init_cmd();
+    }
+
+    /// Dispatch the message for an event from a `remote::RemoteApplier`
+    pub fn dispatch_remote(self: &Rc<Self>, event: &RemoteEvent) {
+        let message = self
+            .remote
+            .borrow()
+            .as_ref()
+            .and_then(|remote| remote.message(event));
+
+        match message {
+            Some((message, true)) => self.dispatch_immediately(&message),
+            Some((message, false)) => self.dispatch(&message),
+            None => console_log!("WARN: No message for the event {:?}", event),
+        }
+    }
+
+    fn render_remote(self: &Rc<Self>) {
+        let mut tree = (self.view)(&self.current_model.borrow());
+
+        if let Some(remote) = &*self.remote.borrow() {
+            remote.render(&mut tree, self.last_tree.borrow().as_ref());
+        }
+
+        self.last_tree.replace(Some(tree));
+    }
+}
+
+#[cfg(feature = "web")]
+impl<Model, Msg> Program<Model, Msg>
+where
+    Model: Debug + Clone + 'static,
+    Msg: PartialEq + Debug + Clone + 'static,
+{
+    fn schedule_render(self: &Rc<Self>) {
+        // There is no window to request animation frames from when running remotely
+        if self.remote.borrow().is_some() {
+            self.render();
+            return;
+        }
+
+        self.dirty.set(true);
+
+        if self.frame_requested.get() {
+            return;
+        }
+
+        let mut on_animation_frame = self.on_animation_frame.borrow_mut();
+        let closure = on_animation_frame.get_or_insert_with(|| {
+            let program = self.clone();
+            Closure::wrap(Box::new(move || {
+                program.frame_requested.set(false);
+                if program.dirty.get() {
+                    program.render();
+                }
+            }) as Box<Fn()>)
+        });
 
         let window = web_sys::window().expect("no global `window` exists");
-        let performance = window
-            .performance()
-            .expect("should have performance on window");
-        let start_time = performance.now();
+        match window.request_animation_frame(closure.as_ref().unchecked_ref()) {
+            Ok(_) => self.frame_requested.set(true),
+            Err(err) => {
+                console_log!("Could not request animation frame: {:?}", err);
+                drop(on_animation_frame);
+                self.render();
+            }
+        }
+    }
 
-        if let Err(err) = render::render(self, &tree, &self.last_tree.borrow()) {
-            console_log!("Got error: {:?}", err);
+    /// Render the view, either by diffing it against the last one, or by hydrating the
+    /// existing children of the mount node
+    fn render_view(self: &Rc<Self>, hydrate: bool) {
+        self.dirty.set(false);
+
+        let mut tree = (self.view)(&self.current_model.borrow());
+
+        // console_log!("New view: {}", tree.to_html_text(0));
+
+        let start_time = utils::now();
+
+        let root = self.root.borrow().clone();
+        if let Some(root) = root {
+            let backend = WebBackend::new();
+            let dispatcher = self.dispatcher();
+            let last_tree = self.last_tree.borrow();
+            let result = match (&*self.delegation.borrow(), hydrate) {
+                (Some(delegation), true) => {
+                    render::hydrate_delegated(&backend, &dispatcher, delegation, &mut tree)
+                        .map(warn_mismatches)
+                }
+                (Some(delegation), false) => render::render_delegated(
+                    &backend,
+                    &dispatcher,
+                    delegation,
+                    &mut tree,
+                    last_tree.as_ref(),
+                ),
+                (None, true) => {
+                    render::hydrate(&backend, &dispatcher, &root, &mut tree).map(warn_mismatches)
+                }
+                (None, false) => {
+                    render::render(&backend, &dispatcher, &root, &mut tree, last_tree.as_ref())
+                }
+            };
+            drop(last_tree);
+            if let Err(err) = result {
+                console_log!("Got error: {:?}", err);
+            }
+        } else {
+            console_log!("Can not render a program that is not started");
+            return;
         }
-        let end_time = performance.now();
+        let end_time = utils::now();
         console_log!("Rendering took {} ms", end_time - start_time);
 
         self.last_tree.replace(Some(tree));
     }
 
-    pub fn start(self: &Rc<Self>) {
-        self.render()
+    fn delegate_from(self: &Rc<Self>, root: &Node) {
+        if !self.delegate.get() {
+            return;
+        }
+        let program = self.clone();
+        let delegation = Delegation::new(root.clone(), move |event| {
+            program.dispatch_delegated(&event)
+        });
+        self.delegation.replace(Some(delegation));
+    }
+
+    // Messages are collected first, as rendering right away needs the tree
+    fn dispatch_delegated(self: &Rc<Self>, event: &Event) {
+        let messages = match (&*self.delegation.borrow(), &*self.last_tree.borrow()) {
+            (Some(delegation), Some(tree)) => {
+                delegate::messages(&WebBackend::new(), delegation.root(), tree, event)
+            }
+            _ => vec![],
+        };
+
+        for (message, immediately) in messages {
+            self.process(&message, immediately);
+        }
+    }
+
+    fn update_subscriptions(self: &Rc<Self>) {
+        // The subscriptions all listen on the window, which there is none of when running remotely
+        if self.remote.borrow().is_some() {
+            return;
+        }
+        let subs = (self.subscriptions)(&self.current_model.borrow());
+
+        if let Err(err) = self
+            .active_subscriptions
+            .borrow_mut()
+            .update(&subs, &self.dispatcher())
+        {
+            console_log!("Got error updating subscriptions: {:?}", err);
+        }
+    }
+
+    /// Render the program into `mount`, and start listening to subscriptions
+    pub fn start<M: Into<Mount>>(self: &Rc<Self>, mount: M) -> Result<(), JsValue> {
+        let root = mount.into().to_node()?;
+        self.delegate_from(&root);
+        self.root.replace(Some(root));
+
+        self.render();
+        self.update_subscriptions();
+        self.run_init_cmd();
+
+        Ok(())
+    }
+
+    /// Like `start`, but takes over markup that is already in `mount`, for example rendered on
+    /// the server with `ssr::render_to_string`. Event listeners are attached to the existing
+    /// nodes, and anything that does not match the view is patched.
+    pub fn hydrate<M: Into<Mount>>(self: &Rc<Self>, mount: M) -> Result<(), JsValue> {
+        let root = mount.into().to_node()?;
+        self.delegate_from(&root);
+        self.root.replace(Some(root));
+
+        self.render_view(true);
+        self.update_subscriptions();
+        self.run_init_cmd();
+
+        Ok(())
+    }
+}
+
+/// Show what did not match the view when hydrating, in debug builds
+#[cfg(feature = "web")]
+fn warn_mismatches(mismatches: Vec<String>) {
+    if cfg!(debug_assertions) {
+        for mismatch in mismatches {
+            console_log!("WARN: Hydration mismatch, {}", mismatch);
+        }
+    }
+}
+
+/// Natively there is nothing to render to, so the view is only kept in `last_tree`
+#[cfg(not(feature = "web"))]
+impl<Model, Msg> Program<Model, Msg>
+where
+    Model: Debug + Clone + 'static,
+    Msg: PartialEq + Debug + Clone + 'static,
+{
+    fn schedule_render(self: &Rc<Self>) {
+        self.render();
+    }
+
+    fn render_view(self: &Rc<Self>, _hydrate: bool) {
+        let tree = (self.view)(&self.current_model.borrow());
+        self.last_tree.replace(Some(tree));
+    }
+
+    fn update_subscriptions(self: &Rc<Self>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::Program;
+    use crate::backend::mock::MockBackend;
+    use crate::cmd::{Cmd, Dispatcher, Effect};
+    use crate::dom_event::EventData;
+    use crate::events::on_click;
+    use crate::html::{button, text, Html};
+    use crate::remote::{RemoteApplier, RemoteEvent};
+    use crate::{Error, Sub};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Add(i32),
+        AddTwice(i32),
+        SaveAndAdd(i32),
+        Failed(String),
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct Model {
+        total: i32,
+        errors: Vec<String>,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Save;
+
+    impl Effect<Msg> for Save {
+        fn run(&self, _: &Dispatcher<Msg>) -> Result<(), Error> {
+            Err(Error::from("could not save"))
+        }
+    }
+
+    fn update(msg: &Msg, model: &mut Model) -> Cmd<Msg> {
+        match msg {
+            Msg::Add(n) => model.total += n,
+            Msg::AddTwice(n) => {
+                return Cmd::batch(vec![Cmd::Message(Msg::Add(*n)), Cmd::Message(Msg::Add(*n))])
+            }
+            Msg::SaveAndAdd(n) => {
+                return Cmd::batch(vec![
+                    Cmd::effect(Save),
+                    Cmd::effect(Save.on_error(|err| Msg::Failed(err.to_string()))),
+                    Cmd::Message(Msg::Add(*n)),
+                ])
+            }
+            Msg::Failed(err) => model.errors.push(err.clone()),
+        }
+        Cmd::none()
+    }
+
+    fn view(model: &Model) -> Html<Msg> {
+        button(
+            &[on_click(Msg::AddTwice(1))],
+            &[text(&model.total.to_string())],
+        )
+    }
+
+    struct Started {
+        program: Rc<Program<Model, Msg>>,
+        applier: Rc<RemoteApplier<MockBackend>>,
+        // Events from the mock, waiting to be sent to the program
+        events: Rc<RefCell<Vec<RemoteEvent>>>,
+    }
+
+    impl Started {
+        fn html(&self) -> String {
+            let backend = self.applier.backend();
+            backend.inner_html(backend.root())
+        }
+
+        fn click(&self) {
+            let backend = self.applier.backend();
+            let button = backend.children(backend.root())[0];
+            backend.dispatch_event(
+                button,
+                EventData {
+                    type_: "click".to_owned(),
+                    ..Default::default()
+                },
+            );
+            for event in self.events.replace(vec![]) {
+                self.program.dispatch_remote(&event);
+            }
+        }
+    }
+
+    // Rendered remotely, onto the mock backend, so that the program does not need a browser
+    fn start() -> Started {
+        let program = Program::new(view, update, |_| Sub::none(), Model::default())
+            .with_init_cmd(Cmd::Message(Msg::Add(1)));
+        let program = Rc::new(program);
+        let events = Rc::new(RefCell::new(vec![]));
+        let applier = {
+            let events = events.clone();
+            let backend = MockBackend::new();
+            let root = backend.root();
+            Rc::new(RemoteApplier::new(backend, root, move |event| {
+                events.borrow_mut().push(event)
+            }))
+        };
+        {
+            let applier = applier.clone();
+            program.start_remote(move |patches| applier.apply(&patches).unwrap());
+        }
+        Started {
+            program,
+            applier,
+            events,
+        }
+    }
+
+    #[test]
+    fn messages_from_commands_come_back_into_update() {
+        let started = start();
+        assert_eq!(started.program.current_model.borrow().total, 1);
+        assert_eq!(started.html(), "<button>1</button>");
+
+        started.program.dispatch(&Msg::AddTwice(2));
+        assert_eq!(started.program.current_model.borrow().total, 5);
+        assert_eq!(started.html(), "<button>5</button>");
+    }
+
+    #[test]
+    fn events_are_dispatched_and_rendered() {
+        let started = start();
+
+        started.click();
+        assert_eq!(started.program.current_model.borrow().total, 3);
+        assert_eq!(started.html(), "<button>3</button>");
+    }
+
+    #[test]
+    fn commands_in_a_batch_run_after_one_fails() {
+        let program = start().program;
+
+        program.dispatch(&Msg::SaveAndAdd(2));
+        assert_eq!(
+            *program.current_model.borrow(),
+            Model {
+                total: 3,
+                errors: vec!["could not save".to_owned()],
+            }
+        );
     }
 }
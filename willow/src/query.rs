@@ -0,0 +1,437 @@
+//! Find nodes in an `Html` tree and read what they would render, to test views without a DOM.
+//!
+//! Selectors are a subset of CSS: `*` and tag names, `.class`, `#id`, `[key=value]` for the
+//! `key` attribute, and `[name]` or `[name=value]` for other properties. They can be combined,
+//! like `li.completed`, and nested with the descendant (`ul li`) and child (`ul > li`)
+//! combinators. Several selectors can be given separated by `,`.
+
+use crate::{
+    html::{Attribute, EventToMessage, Html, HtmlTag, PropertyValue},
+    Error,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector(Vec<Complex>);
+
+// Compounds from the outermost to the node itself, each with the combinator before it
+#[derive(Clone, Debug, PartialEq)]
+struct Complex(Vec<(Combinator, Compound)>);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Compound {
+    tag: Option<String>,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Class(String),
+    Id(String),
+    Key(String),
+    Property(String, Option<String>),
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Selector, Error> {
+        let complexes = split_groups(selector)
+            .into_iter()
+            .map(parse_complex)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Error::from(format!("Invalid selector {:?}: {}", selector, err)))?;
+        Ok(Selector(complexes))
+    }
+
+    /// Whether `tag` matches, where `ancestors` are the tags above it, starting with the root
+    pub fn matches<Msg>(&self, tag: &HtmlTag<Msg>, ancestors: &[&HtmlTag<Msg>]) -> bool {
+        self.0
+            .iter()
+            .any(|complex| matches_complex(&complex.0, tag, ancestors))
+    }
+}
+
+// Split at the `,` that are not inside an attribute selector, like `[placeholder="a,b"]`
+fn split_groups(selector: &str) -> Vec<&str> {
+    let mut groups = vec![];
+    let mut start = 0;
+    let mut in_brackets = false;
+    for (index, c) in selector.char_indices() {
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            ',' if !in_brackets => {
+                groups.push(&selector[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    groups.push(&selector[start..]);
+    groups
+}
+
+fn parse_complex(selector: &str) -> Result<Complex, String> {
+    let mut compounds = vec![];
+    let mut combinator = Combinator::Descendant;
+
+    // Make `>` its own word, whatever the whitespace around it
+    for word in selector.replace('>', " > ").split_whitespace() {
+        if word == ">" {
+            if compounds.is_empty() || combinator == Combinator::Child {
+                return Err("`>` has to be between two selectors".to_owned());
+            }
+            combinator = Combinator::Child;
+        } else {
+            compounds.push((combinator, parse_compound(word)?));
+            combinator = Combinator::Descendant;
+        }
+    }
+
+    if compounds.is_empty() {
+        return Err("empty selector".to_owned());
+    }
+    if combinator == Combinator::Child {
+        return Err("`>` has to be between two selectors".to_owned());
+    }
+    Ok(Complex(compounds))
+}
+
+fn parse_compound(word: &str) -> Result<Compound, String> {
+    let is_delimiter = |c| c == '.' || c == '#' || c == '[';
+
+    let end = word.find(is_delimiter).unwrap_or(word.len());
+    let (tag, mut rest) = word.split_at(end);
+    let tag = match tag {
+        "" | "*" => None,
+        tag => Some(tag.to_owned()),
+    };
+
+    let mut parts = vec![];
+    while !rest.is_empty() {
+        let (kind, after) = rest.split_at(1);
+        let part = if kind == "[" {
+            let end = after
+                .find(']')
+                .ok_or_else(|| format!("missing `]` in {:?}", word))?;
+            let (inner, after) = after.split_at(end);
+            rest = &after[1..];
+
+            let mut pair = inner.splitn(2, '=');
+            let name = pair.next().unwrap_or_default().trim();
+            let value = pair.next().map(|value| {
+                value
+                    .trim()
+                    .trim_matches(|c| c == '"' || c == '\'')
+                    .to_owned()
+            });
+            match (name, value) {
+                ("", _) => return Err(format!("missing attribute name in {:?}", word)),
+                ("key", Some(value)) => Part::Key(value),
+                (name, value) => Part::Property(name.to_owned(), value),
+            }
+        } else {
+            let end = after.find(is_delimiter).unwrap_or(after.len());
+            let (name, after) = after.split_at(end);
+            rest = after;
+
+            if name.is_empty() {
+                return Err(format!("missing name after `{}` in {:?}", kind, word));
+            }
+            if kind == "." {
+                Part::Class(name.to_owned())
+            } else {
+                Part::Id(name.to_owned())
+            }
+        };
+        parts.push(part);
+    }
+
+    Ok(Compound { tag, parts })
+}
+
+fn matches_complex<Msg>(
+    compounds: &[(Combinator, Compound)],
+    tag: &HtmlTag<Msg>,
+    ancestors: &[&HtmlTag<Msg>],
+) -> bool {
+    let ((combinator, compound), outer) = match compounds.split_last() {
+        Some(last) => last,
+        None => return true,
+    };
+    if !matches_compound(compound, tag) {
+        return false;
+    }
+    if outer.is_empty() {
+        return true;
+    }
+
+    match combinator {
+        Combinator::Child => match ancestors.split_last() {
+            Some((parent, above)) => matches_complex(outer, parent, above),
+            None => false,
+        },
+        Combinator::Descendant => (0..ancestors.len())
+            .rev()
+            .any(|index| matches_complex(outer, ancestors[index], &ancestors[..index])),
+    }
+}
+
+fn matches_compound<Msg>(compound: &Compound, tag: &HtmlTag<Msg>) -> bool {
+    if let Some(name) = &compound.tag {
+        if !name.eq_ignore_ascii_case(&tag.tag) {
+            return false;
+        }
+    }
+
+    compound.parts.iter().all(|part| match part {
+        Part::Class(class) => tag.has_class(class),
+        Part::Id(id) => tag.property("id") == Some(&PropertyValue::String(id.clone())),
+        Part::Key(key) => tag.key() == Some(key.as_str()),
+        Part::Property(name, None) => match tag.property(name) {
+            Some(PropertyValue::Bool(value)) => *value,
+            Some(PropertyValue::String(_)) => true,
+            None => false,
+        },
+        Part::Property(name, Some(value)) => {
+            tag.property(name) == Some(&PropertyValue::String(value.clone()))
+        }
+    })
+}
+
+fn find_all<'a, Msg>(
+    html: &'a Html<Msg>,
+    selector: &Selector,
+    ancestors: &mut Vec<&'a HtmlTag<Msg>>,
+    found: &mut Vec<&'a HtmlTag<Msg>>,
+) {
+    if let Html::Tag(tag) = html {
+        if selector.matches(tag, ancestors) {
+            found.push(tag);
+        }
+        ancestors.push(tag);
+        for child in &tag.children {
+            find_all(child, selector, ancestors, found);
+        }
+        ancestors.pop();
+    }
+}
+
+// The first tag that matches, after the tags above it
+fn find_path<'a, Msg>(
+    html: &'a Html<Msg>,
+    selector: &Selector,
+    path: &mut Vec<&'a HtmlTag<Msg>>,
+) -> bool {
+    if let Html::Tag(tag) = html {
+        let matches = selector.matches(tag, path);
+        path.push(tag);
+        if matches
+            || tag
+                .children
+                .iter()
+                .any(|child| find_path(child, selector, path))
+        {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+fn parse(selector: &str) -> Selector {
+    match Selector::parse(selector) {
+        Ok(selector) => selector,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+impl<Msg> Html<Msg> {
+    /// The nodes that match `selector`, including this one, in document order. Panics if the
+    /// selector is not valid.
+    pub fn find_all(&self, selector: &str) -> Vec<&HtmlTag<Msg>> {
+        let mut found = vec![];
+        find_all(self, &parse(selector), &mut vec![], &mut found);
+        found
+    }
+
+    /// The first node that matches `selector`, see `find_all`
+    pub fn find(&self, selector: &str) -> Option<&HtmlTag<Msg>> {
+        self.find_all(selector).into_iter().next()
+    }
+
+    /// The first node that matches `selector` like `find`, after the nodes above it, starting
+    /// with this one. Events bubble up this way.
+    pub fn find_path(&self, selector: &str) -> Option<Vec<&HtmlTag<Msg>>> {
+        let mut path = vec![];
+        if find_path(self, &parse(selector), &mut path) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// The text of this node and everything below it
+    pub fn text_content(&self) -> String {
+        match self {
+            Html::Text(text) => text.clone(),
+            Html::Tag(tag) => tag.text_content(),
+        }
+    }
+}
+
+impl<Msg> HtmlTag<Msg> {
+    /// The nodes below this one that match `selector`, in document order. Like in the DOM, the
+    /// selector may match this node and its ancestors, but only the nodes below are returned.
+    /// Panics if the selector is not valid.
+    pub fn find_all(&self, selector: &str) -> Vec<&HtmlTag<Msg>> {
+        let selector = parse(selector);
+        let mut found = vec![];
+        for child in &self.children {
+            find_all(child, &selector, &mut vec![self], &mut found);
+        }
+        found
+    }
+
+    /// The first node below this one that matches `selector`, see `find_all`
+    pub fn find(&self, selector: &str) -> Option<&HtmlTag<Msg>> {
+        self.find_all(selector).into_iter().next()
+    }
+
+    pub fn text_content(&self) -> String {
+        self.children.iter().map(Html::text_content).collect()
+    }
+
+    /// The value the property `name` is set to, like `"className"` or `"checked"`
+    pub fn property(&self, name: &str) -> Option<&PropertyValue> {
+        // Later attributes override earlier ones when rendered
+        self.attrs.iter().rev().find_map(|attr| match attr {
+            Attribute::Property(key, value) if *key == name => Some(value),
+            _ => None,
+        })
+    }
+
+    /// The value of the style `property`, like `"visibility"`
+    pub fn style(&self, property: &str) -> Option<&str> {
+        self.attrs.iter().rev().find_map(|attr| match attr {
+            Attribute::Style(key, value) if key == property => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        match self.property("className") {
+            Some(PropertyValue::String(classes)) => {
+                classes.split_whitespace().any(|name| name == class)
+            }
+            _ => false,
+        }
+    }
+
+    /// The type of each event listened to, with how the message is made
+    pub fn events(&self) -> Vec<(&str, &EventToMessage<Msg>)> {
+        self.attrs
+            .iter()
+            .filter_map(|attr| match attr {
+                Attribute::Event {
+                    type_, to_message, ..
+                } => Some((type_.as_str(), to_message)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Selector;
+    use crate::attributes::{checked, class, class_list, id, key, placeholder, style, type_};
+    use crate::decode;
+    use crate::events::on_click;
+    use crate::html::{div, input, label, li, span, text, ul, EventToMessage, Html};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Delete(i32),
+    }
+
+    fn view() -> Html<Msg> {
+        div(
+            &[id("app"), style("visibility", "hidden")],
+            &[ul(
+                &[class("todo-list")],
+                &[
+                    li(
+                        &[key("1".to_owned()), class_list(&[("completed", true)])],
+                        &[
+                            input(
+                                &[
+                                    class("toggle"),
+                                    type_("checkbox"),
+                                    checked(true),
+                                    placeholder("a,b"),
+                                ],
+                                &[],
+                            ),
+                            label(&[], &[text("Buy "), span(&[], &[text("milk")])]),
+                        ],
+                    ),
+                    li(
+                        &[key("2".to_owned())],
+                        &[div(&[class("destroy"), on_click(Msg::Delete(2))], &[])],
+                    ),
+                ],
+            )],
+        )
+    }
+
+    #[test]
+    fn finds_nodes_with_selectors() {
+        let html = view();
+
+        assert_eq!(html.find_all("li").len(), 2);
+        assert_eq!(html.find_all("#app > ul > li").len(), 2);
+        assert_eq!(html.find_all("#app > li").len(), 0);
+        assert_eq!(html.find_all("div li .destroy").len(), 1);
+        assert_eq!(html.find_all("li.completed, li[key=2]").len(), 2);
+        assert_eq!(html.find_all("input[checked]").len(), 1);
+        assert_eq!(html.find_all("[type=checkbox]").len(), 1);
+        assert_eq!(html.find_all("*").len(), 8);
+        assert_eq!(html.find("ul").unwrap().find_all("ul li").len(), 2);
+        assert_eq!(html.find_all("[placeholder=\"a,b\"]").len(), 1);
+        assert_eq!(html.find_all("li[key=2], [placeholder='a,b']").len(), 2);
+
+        assert!(Selector::parse("li >").is_err());
+        assert!(Selector::parse("li[key=1").is_err());
+        assert!(Selector::parse("li.").is_err());
+    }
+
+    #[test]
+    fn reads_what_nodes_render() {
+        let html = view();
+        let completed = html.find("li.completed").unwrap();
+
+        assert_eq!(completed.find("label").unwrap().text_content(), "Buy milk");
+        assert_eq!(completed.property("className"), Some(&"completed".into()));
+        assert_eq!(
+            completed.find(".toggle").unwrap().property("checked"),
+            Some(&true.into())
+        );
+        assert_eq!(
+            html.find("#app").unwrap().style("visibility"),
+            Some("hidden")
+        );
+        assert!(!completed.has_class("editing"));
+        assert_eq!(
+            html.find(".destroy").unwrap().events(),
+            vec![(
+                "click",
+                &EventToMessage::Decoder(decode::succeed(Msg::Delete(2)))
+            )]
+        );
+    }
+}
@@ -0,0 +1,587 @@
+//! Run `update`, `view` and the diffing somewhere else than the DOM, for example in a Web
+//! Worker, so that they do not stall the main thread.
+//!
+//! `RemoteView` turns the patches of each render into `RemotePatch`es, which can be serialised
+//! and sent to the main thread. There a `RemoteApplier` applies them to the DOM. Listeners are
+//! referred to by id, and the events they get are sent back as `RemoteEvent`s, to be dispatched
+//! with `Program::dispatch_remote`. How the patches and events are sent, for example with
+//! `postMessage` and `serde_json`, is up to the app.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::rc::Rc;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    backend::Backend,
+    diff::{diff, Patch, Path},
+    dom_event::{Event, EventData},
+    html::{Attribute, EventOptions, EventToMessage, Html, PropertyValue},
+    render::{move_child, node_at, parent_and_node_at},
+    Error,
+};
+
+pub type ListenerId = u32;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RemoteNode {
+    Element {
+        tag: String,
+        attributes: Vec<RemoteAttribute>,
+        children: Vec<RemoteNode>,
+    },
+    Text(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RemoteAttribute {
+    Property(String, PropertyValue),
+    Style(String, String),
+    Listener {
+        id: ListenerId,
+        type_: String,
+        options: EventOptions,
+    },
+}
+
+/// A `diff::Patch` that can be serialised
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RemotePatch {
+    Insert {
+        parent: Path,
+        index: usize,
+        node: RemoteNode,
+    },
+    Replace {
+        path: Path,
+        node: RemoteNode,
+    },
+    Remove {
+        path: Path,
+    },
+    Move {
+        parent: Path,
+        from: usize,
+        to: usize,
+    },
+    SetText {
+        path: Path,
+        text: String,
+    },
+    AddAttribute {
+        path: Path,
+        attribute: RemoteAttribute,
+    },
+    RemoveAttribute {
+        path: Path,
+        attribute: RemoteAttribute,
+    },
+    /// The listeners were on nodes that have been removed, and will not be used again
+    DropListeners(Vec<ListenerId>),
+}
+
+/// An event for the listener with the id `listener`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RemoteEvent {
+    pub listener: ListenerId,
+    pub data: EventData,
+}
+
+/// The side that renders the view. It keeps track of which listener each id belongs to.
+pub struct RemoteView<Msg> {
+    send: Box<Fn(Vec<RemotePatch>)>,
+    next_id: Cell<ListenerId>,
+    listeners: RefCell<HashMap<ListenerId, EventToMessage<Msg>>>,
+}
+
+impl<Msg> RemoteView<Msg>
+where
+    Msg: PartialEq + Debug + Clone + 'static,
+{
+    pub fn new<F>(send: F) -> Self
+    where
+        F: Fn(Vec<RemotePatch>) + 'static,
+    {
+        RemoteView {
+            send: Box::new(send),
+            next_id: Cell::new(0),
+            listeners: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Send the patches that turn `old` into `new`, or that create `new` if nothing is rendered
+    /// yet. Like with `render::render`, the events in `new` get listener handles of their own.
+    pub fn render(&self, new: &mut Html<Msg>, old: Option<&Html<Msg>>) {
+        new.own_listeners();
+        let patches = match old {
+            Some(old) => diff(old, new),
+            None => vec![Patch::Insert {
+                parent: vec![],
+                index: 0,
+                html: new.clone(),
+            }],
+        };
+
+        let mut remote_patches = patches
+            .iter()
+            .filter_map(|patch| self.encode_patch(patch))
+            .collect::<Vec<_>>();
+
+        let dropped = self.drop_unused_listeners(new);
+        if !dropped.is_empty() {
+            remote_patches.push(RemotePatch::DropListeners(dropped));
+        }
+
+        if !remote_patches.is_empty() {
+            (self.send)(remote_patches);
+        }
+    }
+
+    /// The message for `event`, and whether it should be rendered right away, see
+    /// `Program::dispatch_immediately`
+    pub fn message(&self, event: &RemoteEvent) -> Option<(Msg, bool)> {
+        let listeners = self.listeners.borrow();
+        let to_message = listeners.get(&event.listener)?;
+        let message = to_message.to_message(&Event::from_data(event.data.clone()))?;
+        Some((message, to_message.is_input()))
+    }
+
+    fn encode_patch(&self, patch: &Patch<Msg>) -> Option<RemotePatch> {
+        Some(match patch {
+            Patch::Insert {
+                parent,
+                index,
+                html,
+            } => RemotePatch::Insert {
+                parent: parent.clone(),
+                index: *index,
+                node: self.encode_node(html),
+            },
+            Patch::Replace { path, html } => RemotePatch::Replace {
+                path: path.clone(),
+                node: self.encode_node(html),
+            },
+            Patch::Remove { path } => RemotePatch::Remove { path: path.clone() },
+            Patch::Move { parent, from, to } => RemotePatch::Move {
+                parent: parent.clone(),
+                from: *from,
+                to: *to,
+            },
+            Patch::SetText { path, text } => RemotePatch::SetText {
+                path: path.clone(),
+                text: text.clone(),
+            },
+            Patch::AddAttribute { path, attribute } => RemotePatch::AddAttribute {
+                path: path.clone(),
+                attribute: self.encode_attribute(attribute)?,
+            },
+            Patch::RemoveAttribute { path, attribute } => RemotePatch::RemoveAttribute {
+                path: path.clone(),
+                attribute: self.encode_removed_attribute(attribute)?,
+            },
+            // The listener stays as it is on the other side
+            Patch::UpdateListener { attribute, .. } => {
+                if let Attribute::Event {
+                    listener,
+                    to_message,
+                    ..
+                } = attribute
+                {
+                    let id = listener.get::<ListenerId>()?;
+                    self.listeners.borrow_mut().insert(id, to_message.clone());
+                }
+                return None;
+            }
+            Patch::KeepListener { from, to } => {
+                from.move_to(to);
+                return None;
+            }
+        })
+    }
+
+    fn encode_node(&self, html: &Html<Msg>) -> RemoteNode {
+        match html {
+            Html::Tag(tag) => RemoteNode::Element {
+                tag: tag.tag.clone(),
+                attributes: tag
+                    .attrs
+                    .iter()
+                    .filter_map(|attr| self.encode_attribute(attr))
+                    .collect(),
+                children: tag
+                    .children
+                    .iter()
+                    .map(|child| self.encode_node(child))
+                    .collect(),
+            },
+            Html::Text(text) => RemoteNode::Text(text.clone()),
+        }
+    }
+
+    /// Keys only matter when diffing, and are left out
+    fn encode_attribute(&self, attribute: &Attribute<Msg>) -> Option<RemoteAttribute> {
+        match attribute {
+            Attribute::Event {
+                listener,
+                type_,
+                options,
+                to_message,
+            } => {
+                let id = self.next_id.get();
+                self.next_id.set(id.wrapping_add(1));
+                listener.set(id);
+                self.listeners.borrow_mut().insert(id, to_message.clone());
+
+                Some(RemoteAttribute::Listener {
+                    id,
+                    type_: type_.clone(),
+                    options: *options,
+                })
+            }
+            Attribute::Property(name, value) => {
+                Some(RemoteAttribute::Property(name.to_string(), value.clone()))
+            }
+            Attribute::Style(property, value) => {
+                Some(RemoteAttribute::Style(property.clone(), value.clone()))
+            }
+            Attribute::Key(_) => None,
+        }
+    }
+
+    fn encode_removed_attribute(&self, attribute: &Attribute<Msg>) -> Option<RemoteAttribute> {
+        match attribute {
+            Attribute::Event {
+                listener,
+                type_,
+                options,
+                ..
+            } => {
+                let id = listener.take::<ListenerId>()?;
+                self.listeners.borrow_mut().remove(&id);
+
+                Some(RemoteAttribute::Listener {
+                    id,
+                    type_: type_.clone(),
+                    options: *options,
+                })
+            }
+            attribute => self.encode_attribute(attribute),
+        }
+    }
+
+    /// Forget the listeners that are not in `tree`, and return their ids
+    fn drop_unused_listeners(&self, tree: &Html<Msg>) -> Vec<ListenerId> {
+        let mut used = HashSet::new();
+        collect_listeners(tree, &mut used);
+
+        let mut listeners = self.listeners.borrow_mut();
+        let mut dropped = listeners
+            .keys()
+            .filter(|id| !used.contains(*id))
+            .cloned()
+            .collect::<Vec<_>>();
+        dropped.sort();
+        for id in &dropped {
+            listeners.remove(id);
+        }
+        dropped
+    }
+}
+
+fn collect_listeners<Msg>(html: &Html<Msg>, ids: &mut HashSet<ListenerId>) {
+    if let Html::Tag(tag) = html {
+        for attr in &tag.attrs {
+            if let Some(id) = attr.listener().and_then(|listener| listener.get()) {
+                ids.insert(id);
+            }
+        }
+        for child in &tag.children {
+            collect_listeners(child, ids);
+        }
+    }
+}
+
+/// The side that has the DOM. Events are sent back with `send`.
+pub struct RemoteApplier<B: Backend> {
+    backend: B,
+    root: B::Node,
+    send: Rc<Fn(RemoteEvent)>,
+    // The node and type of each listener, to be able to remove it again
+    listeners: RefCell<HashMap<ListenerId, AddedListener<B>>>,
+}
+
+type AddedListener<B> = (
+    <B as Backend>::Node,
+    String,
+    EventOptions,
+    <B as Backend>::Listener,
+);
+
+impl<B: Backend> RemoteApplier<B> {
+    /// Apply patches to the children of `root`
+    pub fn new<F>(backend: B, root: B::Node, send: F) -> Self
+    where
+        F: Fn(RemoteEvent) + 'static,
+    {
+        RemoteApplier {
+            backend,
+            root,
+            send: Rc::new(send),
+            listeners: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn apply(&self, patches: &[RemotePatch]) -> Result<(), Error> {
+        for patch in patches {
+            self.apply_patch(patch)?;
+        }
+        Ok(())
+    }
+
+    fn apply_patch(&self, patch: &RemotePatch) -> Result<(), Error> {
+        let backend = &self.backend;
+        match patch {
+            RemotePatch::Insert {
+                parent,
+                index,
+                node,
+            } => {
+                let parent = node_at(backend, &self.root, parent)?;
+                let node = self.create_node(node)?;
+                let reference = backend.child(&parent, *index);
+                backend.insert_before(&parent, &node, reference.as_ref())?;
+            }
+            RemotePatch::Replace { path, node } => {
+                let (parent, old) = parent_and_node_at(backend, &self.root, path)?;
+                let node = self.create_node(node)?;
+                backend.replace_child(&parent, &node, &old)?;
+            }
+            RemotePatch::Remove { path } => {
+                let (parent, node) = parent_and_node_at(backend, &self.root, path)?;
+                backend.remove_child(&parent, &node)?;
+            }
+            RemotePatch::Move { parent, from, to } => {
+                move_child(backend, &node_at(backend, &self.root, parent)?, *from, *to)?;
+            }
+            RemotePatch::SetText { path, text } => {
+                backend.set_text(&node_at(backend, &self.root, path)?, text)?;
+            }
+            RemotePatch::AddAttribute { path, attribute } => {
+                self.add_attribute(&node_at(backend, &self.root, path)?, attribute)?;
+            }
+            RemotePatch::RemoveAttribute { path, attribute } => {
+                let node = node_at(backend, &self.root, path)?;
+                match attribute {
+                    RemoteAttribute::Property(name, _) => backend.remove_property(&node, name)?,
+                    RemoteAttribute::Style(property, _) => backend.remove_style(&node, property)?,
+                    RemoteAttribute::Listener { id, .. } => self.remove_listener(*id)?,
+                }
+            }
+            RemotePatch::DropListeners(ids) => {
+                for id in ids {
+                    self.remove_listener(*id)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_node(&self, node: &RemoteNode) -> Result<B::Node, Error> {
+        match node {
+            RemoteNode::Element {
+                tag,
+                attributes,
+                children,
+            } => {
+                let element = self.backend.create_element(tag)?;
+                for attribute in attributes {
+                    self.add_attribute(&element, attribute)?;
+                }
+                for child in children {
+                    let child = self.create_node(child)?;
+                    self.backend.insert_before(&element, &child, None)?;
+                }
+                Ok(element)
+            }
+            RemoteNode::Text(text) => self.backend.create_text(text),
+        }
+    }
+
+    fn add_attribute(&self, node: &B::Node, attribute: &RemoteAttribute) -> Result<(), Error> {
+        match attribute {
+            RemoteAttribute::Property(name, value) => {
+                self.backend.set_property(node, name, value)?;
+            }
+            RemoteAttribute::Style(property, value) => {
+                self.backend.set_style(node, property, value)?;
+            }
+            RemoteAttribute::Listener { id, type_, options } => {
+                let id = *id;
+                let send = self.send.clone();
+                let EventOptions {
+                    prevent_default,
+                    stop_propagation,
+                    ..
+                } = *options;
+                // Default actions and propagation have to be stopped right away, before the
+                // event has made it to the other side
+                let handler = Rc::new(move |event: Event| {
+                    if prevent_default {
+                        event.prevent_default();
+                    }
+                    if stop_propagation {
+                        event.stop_propagation();
+                    }
+                    send(RemoteEvent {
+                        listener: id,
+                        data: event.to_data(),
+                    });
+                });
+
+                let listener = self.backend.add_listener(node, type_, options, handler)?;
+                self.listeners
+                    .borrow_mut()
+                    .insert(id, (node.clone(), type_.clone(), *options, listener));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_listener(&self, id: ListenerId) -> Result<(), Error> {
+        let removed = self.listeners.borrow_mut().remove(&id);
+        if let Some((node, type_, options, listener)) = removed {
+            self.backend
+                .remove_listener(&node, &type_, &options, listener)?;
+        } else {
+            console_log!("WARN: Could not find listener {} to remove", id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{RemoteApplier, RemoteAttribute, RemoteEvent, RemoteNode, RemotePatch, RemoteView};
+    use crate::attributes::class;
+    use crate::backend::mock::MockBackend;
+    use crate::dom_event::EventData;
+    use crate::events::on_click;
+    use crate::html::{button, div, text, Html};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Clicked,
+    }
+
+    fn view(buttons: usize) -> Html<Msg> {
+        div(
+            &[class("buttons")],
+            &(0..buttons)
+                .map(|_| button(&[on_click(Msg::Clicked)], &[text("Click")]))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Send everything through JSON, like it would be between a worker and the main thread
+    fn roundtrip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn applies_patches_and_sends_events_back() {
+        let sent = Rc::new(RefCell::new(vec![]));
+        let remote = {
+            let sent = sent.clone();
+            RemoteView::new(move |patches: Vec<RemotePatch>| {
+                sent.borrow_mut().push(roundtrip(&patches))
+            })
+        };
+        let events = Rc::new(RefCell::new(vec![]));
+        let applier = {
+            let events = events.clone();
+            let backend = MockBackend::new();
+            let root = backend.root();
+            RemoteApplier::new(backend, root, move |event: RemoteEvent| {
+                events.borrow_mut().push(roundtrip(&event))
+            })
+        };
+
+        let mut first = view(2);
+        remote.render(&mut first, None);
+        let mut second = view(1);
+        remote.render(&mut second, Some(&first));
+
+        for patches in sent.borrow().iter() {
+            applier.apply(patches).unwrap();
+        }
+        let backend = applier.backend();
+        let root = backend.root();
+        assert_eq!(
+            backend.inner_html(root),
+            "<div className=\"buttons\"><button>Click</button></div>"
+        );
+        // The listener of the removed button is dropped on both sides
+        assert_eq!(
+            sent.borrow()[1].last(),
+            Some(&RemotePatch::DropListeners(vec![1]))
+        );
+
+        let button = backend.children(backend.children(root)[0])[0];
+        backend.dispatch_event(
+            button,
+            EventData {
+                type_: "click".to_owned(),
+                ..Default::default()
+            },
+        );
+        let event = events.borrow()[0].clone();
+        assert_eq!(remote.message(&event), Some((Msg::Clicked, false)));
+    }
+    #[test]
+    fn cloned_subtrees_get_a_listener_each() {
+        let sent = Rc::new(RefCell::new(vec![]));
+        let remote = {
+            let sent = sent.clone();
+            RemoteView::new(move |patches: Vec<RemotePatch>| sent.borrow_mut().extend(patches))
+        };
+        let button = button(&[on_click(Msg::Clicked)], &[]);
+
+        remote.render(&mut div(&[], &[button.clone(), button]), None);
+
+        let ids = match &sent.borrow()[0] {
+            RemotePatch::Insert {
+                node: RemoteNode::Element { children, .. },
+                ..
+            } => children
+                .iter()
+                .map(|child| match child {
+                    RemoteNode::Element { attributes, .. } => match attributes[0] {
+                        RemoteAttribute::Listener { id, .. } => id,
+                        _ => panic!("expected a listener"),
+                    },
+                    _ => panic!("expected an element"),
+                })
+                .collect::<Vec<_>>(),
+            patch => panic!("expected an insert, got {:?}", patch),
+        };
+        assert_eq!(ids, vec![0, 1]);
+        for id in ids {
+            let event = RemoteEvent {
+                listener: id,
+                data: Default::default(),
+            };
+            assert_eq!(remote.message(&event), Some((Msg::Clicked, false)));
+        }
+    }
+}
@@ -1,221 +1,451 @@
+//! Applies the patches from `diff` through a `Backend`
+
+use std::cell::RefCell;
 use std::fmt::Debug;
 use std::rc::Rc;
 
-use itertools::{EitherOrBoth, Itertools};
-use js_sys::Reflect;
-use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
-use web_sys::{self, Document, HtmlElement, Node};
-
 use crate::{
-    html::{Attribute, EventToMessage, Html, HtmlTag, PropertyValue},
-    program::Program,
+    backend::Backend,
+    cmd::Dispatcher,
+    delegate::{delegated_type, Delegation},
+    diff::{diff, Patch},
+    dom_event::Event,
+    html::{Attribute, EventOptions, EventToMessage, Html, HtmlTag},
+    Error,
 };
 
-pub fn render<Msg: PartialEq + Debug + Clone + 'static, Model: Debug + Clone + 'static>(
-    program: &Rc<Program<Model, Msg>>,
-    new_tree: &Html<Msg>,
-    old_tree: &Option<Html<Msg>>,
-) -> Result<(), JsValue> {
-    let window = web_sys::window().expect("no global `window` exists");
-    let document = window.document().expect("should have a document on window");
-    // let body = document.body().expect("No Body");
-    let parent = document
-        .get_element_by_id("app")
-        .expect("did not find an app element");
-
-    let parent: Node = parent.into();
-
-    let mut renderer = Renderer {
-        document: document,
-        program: program.clone(),
-        to_remove: vec![],
+/// Update the children of `parent`, which were rendered from `old_tree`, to match `new_tree`.
+/// Messages from event listeners are sent to `dispatcher`. The events in `new_tree` get
+/// listener handles of their own first, see `Html::own_listeners`.
+pub fn render<B: Backend, Msg: PartialEq + Debug + Clone + 'static>(
+    backend: &B,
+    dispatcher: &Dispatcher<Msg>,
+    parent: &B::Node,
+    new_tree: &mut Html<Msg>,
+    old_tree: Option<&Html<Msg>>,
+) -> Result<(), Error> {
+    new_tree.own_listeners();
+    apply(backend, dispatcher, parent, &patches(new_tree, old_tree))
+}
+
+/// Like `render`, into the root of `delegation`. Events are listened for on the root, and
+/// `delegate::messages` finds the handlers for them in `new_tree`.
+pub fn render_delegated<B: Backend, Msg: PartialEq + Debug + Clone + 'static>(
+    backend: &B,
+    dispatcher: &Dispatcher<Msg>,
+    delegation: &Delegation<B>,
+    new_tree: &mut Html<Msg>,
+    old_tree: Option<&Html<Msg>>,
+) -> Result<(), Error> {
+    new_tree.own_listeners();
+    let renderer = Renderer {
+        backend,
+        dispatcher: dispatcher.clone(),
+        delegation: Some(delegation),
+        mismatches: RefCell::new(vec![]),
     };
-    // console_log!("New Tree: \n{:#?}\n\nOld Tree: \n{:#?}", new_tree, old_tree);
 
-    // TODO: We should probably not assume that the number here is 0
-    renderer.update_element(&parent, Some(new_tree), old_tree.as_ref(), 0)?;
-    renderer.remove_to_remove()?;
+    for patch in &patches(new_tree, old_tree) {
+        renderer.apply_patch(delegation.root(), patch)?;
+    }
 
-    // let node = renderer.create_node(new_tree)?;
+    Ok(())
+}
 
-    // AsRef::<Element>::as_ref(&body).set_inner_html("");
-    // AsRef::<Node>::as_ref(&body).append_child(&node)?;
+fn patches<Msg: PartialEq + Clone>(
+    new_tree: &Html<Msg>,
+    old_tree: Option<&Html<Msg>>,
+) -> Vec<Patch<Msg>> {
+    match old_tree {
+        Some(old_tree) => diff(old_tree, new_tree),
+        None => vec![Patch::Insert {
+            parent: vec![],
+            index: 0,
+            html: new_tree.clone(),
+        }],
+    }
+}
 
-    // Manufacture the element we're gonna append
-    // let val = document.create_element("p")?;
-    // val.set_inner_html("Hello from Rust!");
+/// Apply `patches`, with paths starting at `parent`
+pub fn apply<B: Backend, Msg: PartialEq + Debug + Clone + 'static>(
+    backend: &B,
+    dispatcher: &Dispatcher<Msg>,
+    parent: &B::Node,
+    patches: &[Patch<Msg>],
+) -> Result<(), Error> {
+    let renderer = Renderer {
+        backend,
+        dispatcher: dispatcher.clone(),
+        delegation: None,
+        mismatches: RefCell::new(vec![]),
+    };
 
-    // Right now the class inheritance hierarchy of the DOM isn't super
-    // ergonomic, so we manually cast `val: Element` to `&Node` to call the
-    // `append_child` method.
-    // AsRef::<Element>::as_ref(&body).set_inner_html("");
-    // AsRef::<Node>::as_ref(&body).append_child(&node)?;
+    for patch in patches {
+        renderer.apply_patch(parent, patch)?;
+    }
 
     Ok(())
 }
 
-struct Renderer<Model, Msg> {
-    document: Document,
-    program: Rc<Program<Model, Msg>>,
-    to_remove: Vec<(Node, Node)>,
+/// Attach to the markup that is already in `parent` instead of creating new nodes. Like with
+/// `render`, the view is the first child of `parent`, and the other children are left alone.
+/// Nodes that do not match `tree` are patched, and the mismatches are returned, to be shown as
+/// warnings.
+pub fn hydrate<B: Backend, Msg: PartialEq + Debug + Clone + 'static>(
+    backend: &B,
+    dispatcher: &Dispatcher<Msg>,
+    parent: &B::Node,
+    tree: &mut Html<Msg>,
+) -> Result<Vec<String>, Error> {
+    tree.own_listeners();
+    let renderer = Renderer {
+        backend,
+        dispatcher: dispatcher.clone(),
+        delegation: None,
+        mismatches: RefCell::new(vec![]),
+    };
+
+    renderer.hydrate_child(parent, 0, tree)?;
+    Ok(renderer.mismatches.into_inner())
 }
 
-fn eiter_or_both_to_option_tuple<T>(pair: EitherOrBoth<T, T>) -> (Option<T>, Option<T>) {
-    use itertools::EitherOrBoth::{Both, Left, Right};
-    match pair {
-        Both(a, b) => (Some(a), Some(b)),
-        Left(a) => (Some(a), None),
-        Right(b) => (None, Some(b)),
-    }
+/// Like `hydrate`, with the events delegated as in `render_delegated`
+pub fn hydrate_delegated<B: Backend, Msg: PartialEq + Debug + Clone + 'static>(
+    backend: &B,
+    dispatcher: &Dispatcher<Msg>,
+    delegation: &Delegation<B>,
+    tree: &mut Html<Msg>,
+) -> Result<Vec<String>, Error> {
+    tree.own_listeners();
+    let renderer = Renderer {
+        backend,
+        dispatcher: dispatcher.clone(),
+        delegation: Some(delegation),
+        mismatches: RefCell::new(vec![]),
+    };
+
+    renderer.hydrate_child(delegation.root(), 0, tree)?;
+    Ok(renderer.mismatches.into_inner())
 }
 
-fn parents(node: &Node) -> String {
-    let mut result = vec![node.node_name()];
-    let mut node = node.to_owned();
-    while let Some(new_node) = node.parent_node() {
-        result.push(new_node.node_name());
-        node = new_node;
+fn child<B: Backend>(backend: &B, parent: &B::Node, index: usize) -> Result<B::Node, Error> {
+    backend.child(parent, index).ok_or_else(|| {
+        Error::from(format!(
+            "ERROR: Could not find node at index {}, {:?}",
+            index,
+            backend.describe(parent)
+        ))
+    })
+}
+
+/// The node at `path` from `root`, see `diff::Path`
+pub(crate) fn node_at<B: Backend>(
+    backend: &B,
+    root: &B::Node,
+    path: &[usize],
+) -> Result<B::Node, Error> {
+    let mut node = root.clone();
+    for index in path {
+        node = child(backend, &node, *index)?;
     }
-    result.join(" -> ")
+    Ok(node)
 }
 
-impl<Model, Msg> Renderer<Model, Msg>
+pub(crate) fn parent_and_node_at<B: Backend>(
+    backend: &B,
+    root: &B::Node,
+    path: &[usize],
+) -> Result<(B::Node, B::Node), Error> {
+    let (index, parent_path) = path
+        .split_last()
+        .ok_or_else(|| Error::from("The root can not be replaced or removed"))?;
+    let parent = node_at(backend, root, parent_path)?;
+    let node = child(backend, &parent, *index)?;
+    Ok((parent, node))
+}
+
+/// Move child `from` of `parent` so that it ends up as child `to`, see `Patch::Move`
+pub(crate) fn move_child<B: Backend>(
+    backend: &B,
+    parent: &B::Node,
+    from: usize,
+    to: usize,
+) -> Result<(), Error> {
+    let node = child(backend, parent, from)?;
+    // The node is still in the list while looking for the one to insert it before
+    let reference = if to < from {
+        backend.child(parent, to)
+    } else {
+        backend.child(parent, to + 1)
+    };
+    backend.insert_before(parent, &node, reference.as_ref())
+}
+
+macro_rules! hydration_mismatch {
+    ($renderer:expr, $($t:tt)*) => {
+        $renderer.mismatches.borrow_mut().push(format!($($t)*));
+    };
+}
+
+// What the `ListenerHandle` of a rendered event holds. How messages are made is kept in a cell,
+// so that it can be swapped without going through the backend, see `Patch::UpdateListener`.
+struct Registered<L, Msg> {
+    listener: L,
+    to_message: Rc<RefCell<EventToMessage<Msg>>>,
+}
+
+struct Renderer<'a, B: Backend, Msg> {
+    backend: &'a B,
+    dispatcher: Dispatcher<Msg>,
+    // Where events are listened for, when they are delegated
+    delegation: Option<&'a Delegation<B>>,
+    // What did not match the view when hydrating
+    mismatches: RefCell<Vec<String>>,
+}
+
+impl<'a, B, Msg> Renderer<'a, B, Msg>
 where
+    B: Backend,
     Msg: PartialEq + Debug + Clone + 'static,
-    Model: Debug + Clone + 'static,
 {
-    fn update_element(
-        &mut self,
-        parent: &Node,
-        new: Option<&Html<Msg>>,
-        old: Option<&Html<Msg>>,
-        index: u32,
-    ) -> Result<(), JsValue> {
-        match (old, new) {
-            (None, Some(new_html)) => {
-                // Node is added
-                // console_log!("Adding node");
-                parent.append_child(&self.create_node(new_html)?)?;
+    // Whether events of `type_` are handled by the delegation, instead of a listener on the
+    // element
+    fn is_delegated(&self, type_: &str, options: &EventOptions) -> bool {
+        self.delegation.is_some() && delegated_type(type_, options).is_some()
+    }
+
+    fn apply_patch(&self, root: &B::Node, patch: &Patch<Msg>) -> Result<(), Error> {
+        match patch {
+            Patch::Insert {
+                parent,
+                index,
+                html,
+            } => {
+                let parent = node_at(self.backend, root, parent)?;
+                let node = self.create_node(html)?;
+                let reference = self.backend.child(&parent, *index);
+                self.backend
+                    .insert_before(&parent, &node, reference.as_ref())?;
             }
-            (Some(removed), None) => {
-                // console_log!("Removing node");
-                // Node is removed
-                if let Some(child) = parent.child_nodes().item(index) {
-                    // Don't remove childs until after every iteration is finished. If not, the
-                    // indexes will not point to the correct nodes anymore
-                    self.to_remove.push((parent.clone(), child));
-                } else {
-                    console_log!(
-                        "Could not find node with index {} when removing {}",
-                        index,
-                        removed.to_html_text(0)
-                    );
-                }
+            Patch::Replace { path, html } => {
+                let (parent, node) = parent_and_node_at(self.backend, root, path)?;
+                let new_node = self.create_node(html)?;
+                self.backend.replace_child(&parent, &new_node, &node)?;
+            }
+            Patch::Remove { path } => {
+                let (parent, node) = parent_and_node_at(self.backend, root, path)?;
+                self.backend.remove_child(&parent, &node)?;
+            }
+            Patch::Move { parent, from, to } => {
+                move_child(
+                    self.backend,
+                    &node_at(self.backend, root, parent)?,
+                    *from,
+                    *to,
+                )?;
             }
-            (Some(old), Some(new)) => match (old, new) {
-                (Html::Tag(old_tag), Html::Tag(new_tag))
-                    if old_tag.tag == new_tag.tag && old_tag.key() == new_tag.key() =>
+            Patch::SetText { path, text } => {
+                self.backend
+                    .set_text(&node_at(self.backend, root, path)?, text)?;
+            }
+            Patch::AddAttribute { path, attribute } => {
+                self.add_attribute(&node_at(self.backend, root, path)?, attribute)?;
+            }
+            Patch::RemoveAttribute { path, attribute } => {
+                self.remove_attribute(&node_at(self.backend, root, path)?, attribute)?;
+            }
+            Patch::UpdateListener { attribute, .. } => {
+                if let Attribute::Event {
+                    listener,
+                    to_message,
+                    type_,
+                    options,
+                } = attribute
                 {
-                    // console_log!(
-                    //     "Updating {} to {}",
-                    //     old_tag.to_html_text(0),
-                    //     new_tag.to_html_text(0)
-                    // );
-                    if let Some(current_node) = parent.child_nodes().item(index) {
-                        let current_node: HtmlElement = current_node.dyn_into()?;
-                        // We have a node (current_node) that has changed from old_tag to new_tag, though
-                        // the tag is still the same. This means we need to diff children and attributes
-
-                        // First we diff attributes
-                        // We start by removing the ones that are no longer active
-                        for attr in &old_tag.attrs {
-                            if !new_tag.attrs.contains(attr) {
-                                // console_log!("Removing attribute {:?}", attr);
-                                self.remove_attribute(&current_node, attr)?;
-                            }
-
-                            // Move closures over to the new events because we do not want them to be garbage collected
-                            if attr.is_event() {
-                                if let Some(new_attr) =
-                                    new_tag.attrs.iter().filter(|e| e == &attr).next()
-                                {
-                                    if let Some(js_closure) =
-                                        attr.get_js_closure().0.borrow_mut().take()
-                                    {
-                                        new_attr.set_js_closure(js_closure)
-                                    }
-                                }
-                            }
-                        }
-                        // Then we add the ones that are added
-                        for attr in &new_tag.attrs {
-                            if !old_tag.attrs.contains(attr) {
-                                // console_log!("Adding attribute {:?}", attr);
-                                self.add_attribute(&current_node, attr)?;
-                            }
-                        }
-
-                        for (child_index, pair) in old_tag
-                            .children
-                            .iter()
-                            .zip_longest(new_tag.children.iter())
-                            .enumerate()
-                        {
-                            let (old_child, new_child) = eiter_or_both_to_option_tuple(pair);
-
-                            self.update_element(
-                                &current_node.as_ref(),
-                                new_child,
-                                old_child,
-                                child_index as u32,
-                            )?;
-                        }
-                    } else {
-                        return Err(JsValue::from_str(&format!(
-                            "ERROR: Could not find node at index {}, {:?}",
-                            index,
-                            parents(parent)
-                        )));
+                    // Delegated events are looked up in the tree, and need no update
+                    if self.is_delegated(type_, options) {
+                        return Ok(());
+                    }
+                    let updated = listener.with(|registered: &Registered<B::Listener, Msg>| {
+                        registered.to_message.replace(to_message.clone());
+                    });
+                    if updated.is_none() {
+                        console_log!("WARN: Could not find the listener to update");
                     }
                 }
-                (Html::Text(s1), Html::Text(s2)) => {
-                    // Only replace if the text is not the same
-                    if s1 != s2 {
-                        if let Some(child) = parent.child_nodes().item(index) {
-                            parent.replace_child(&self.create_node(new)?, &child)?;
-                        } else {
-                            return Err(JsValue::from_str(&format!(
-                                "ERROR: Could not find node at index {}, {:?}",
-                                index,
-                                parents(parent)
-                            )));
-                        }
+            }
+            Patch::KeepListener { from, to } => from.move_to(to),
+        }
+
+        Ok(())
+    }
+
+    fn hydrate_children(&self, parent: &B::Node, children: &[Html<Msg>]) -> Result<(), Error> {
+        for (index, child) in children.iter().enumerate() {
+            self.hydrate_child(parent, index, child)?;
+        }
+
+        while let Some(node) = self.backend.child(parent, children.len()) {
+            if !self.is_markup_only(&node, None) {
+                hydration_mismatch!(
+                    self,
+                    "removing {} from {}",
+                    self.backend.describe(&node),
+                    self.backend.describe(parent)
+                );
+            }
+            self.backend.remove_child(parent, &node)?;
+        }
+
+        Ok(())
+    }
+
+    /// Hydrate child `index` of `parent` from `html`, after removing what is only in the markup
+    fn hydrate_child(&self, parent: &B::Node, index: usize, html: &Html<Msg>) -> Result<(), Error> {
+        let node = loop {
+            match self.backend.child(parent, index) {
+                Some(node) if self.is_markup_only(&node, Some(html)) => {
+                    self.backend.remove_child(parent, &node)?
+                }
+                node => break node,
+            }
+        };
+
+        match (node, html) {
+            // Empty text leaves nothing in the markup
+            (node, Html::Text(text)) if text.is_empty() => {
+                let new_node = self.create_node(html)?;
+                self.backend.insert_before(parent, &new_node, node.as_ref())
+            }
+            (Some(node), _) => self.hydrate_node(parent, &node, html),
+            (None, _) => {
+                hydration_mismatch!(
+                    self,
+                    "adding {} to {}",
+                    html.to_html_text(0),
+                    self.backend.describe(parent)
+                );
+                let node = self.create_node(html)?;
+                self.backend.insert_before(parent, &node, None)
+            }
+        }
+    }
+
+    // Whether `node` is only in the markup, and not in the view. That is comments, like the ones
+    // `ssr::render_to_string` puts between text nodes, and whitespace between tags.
+    fn is_markup_only(&self, node: &B::Node, html: Option<&Html<Msg>>) -> bool {
+        match (self.backend.text(node), html) {
+            (Some(_), Some(Html::Text(_))) => false,
+            (Some(text), _) => text.trim().is_empty(),
+            (None, _) => self.backend.tag_name(node).is_none(),
+        }
+    }
+
+    fn hydrate_node(
+        &self,
+        parent: &B::Node,
+        node: &B::Node,
+        html: &Html<Msg>,
+    ) -> Result<(), Error> {
+        match (html, self.backend.text(node), self.backend.tag_name(node)) {
+            (Html::Text(text), Some(current), _) => {
+                if &current != text {
+                    hydration_mismatch!(self, "text {:?} should be {:?}", current, text);
+                    self.backend.set_text(node, text)?;
+                }
+            }
+            (Html::Tag(tag), _, Some(ref tag_name)) if tag_name.eq_ignore_ascii_case(&tag.tag) => {
+                self.hydrate_attributes(node, &tag.attrs)?;
+                self.hydrate_children(node, &tag.children)?;
+            }
+            _ => {
+                hydration_mismatch!(
+                    self,
+                    "replacing {} with {} in {}",
+                    self.backend.describe(node),
+                    html.to_html_text(0),
+                    self.backend.describe(parent)
+                );
+                let new_node = self.create_node(html)?;
+                self.backend.replace_child(parent, &new_node, node)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set what `node` does not already have of `attrs`, and remove the properties and styles
+    /// that are not in `attrs`
+    fn hydrate_attributes(&self, node: &B::Node, attrs: &[Attribute<Msg>]) -> Result<(), Error> {
+        for attr in attrs {
+            match attr {
+                Attribute::Property(key, value) => {
+                    let current = self.backend.property(node, key);
+                    if current.as_ref() != Some(value) {
+                        hydration_mismatch!(
+                            self,
+                            "{} of {} is {:?}, not {:?}",
+                            key,
+                            self.backend.describe(node),
+                            current,
+                            value
+                        );
+                        self.backend.set_property(node, key, value)?;
                     }
                 }
-                _ => {
-                    if let Some(child) = parent.child_nodes().item(index) {
-                        parent.replace_child(&self.create_node(new)?, &child)?;
-                    } else {
-                        return Err(JsValue::from_str(&format!(
-                            "ERROR: Could not find node at index {}, {:?}",
-                            index,
-                            parents(parent)
-                        )));
+                Attribute::Style(property, value) => {
+                    let current = self.backend.style(node, property).unwrap_or_default();
+                    if &current != value {
+                        hydration_mismatch!(
+                            self,
+                            "style {} of {} is {:?}, not {:?}",
+                            property,
+                            self.backend.describe(node),
+                            current,
+                            value
+                        );
+                        self.backend.set_style(node, property, value)?;
                     }
                 }
-            },
-            (None, None) => {
-                // Should never happen, but if it happens we can just do nothing and it will be okay
+                Attribute::Event { .. } | Attribute::Key(_) => self.add_attribute(node, attr)?,
+            }
+        }
+
+        for name in self.backend.property_names(node) {
+            let in_view = attrs.iter().any(|attr| match attr {
+                Attribute::Property(key, _) => *key == name,
+                _ => false,
+            });
+            if !in_view {
+                hydration_mismatch!(
+                    self,
+                    "removing {} from {}",
+                    name,
+                    self.backend.describe(node)
+                );
+                self.backend.remove_property(node, &name)?;
+            }
+        }
+        for name in self.backend.style_names(node) {
+            let in_view = attrs.iter().any(|attr| match attr {
+                Attribute::Style(property, _) => *property == name,
+                _ => false,
+            });
+            if !in_view {
+                hydration_mismatch!(
+                    self,
+                    "removing style {} from {}",
+                    name,
+                    self.backend.describe(node)
+                );
+                self.backend.remove_style(node, &name)?;
             }
         }
 
         Ok(())
     }
 
-    fn create_node(&self, input: &Html<Msg>) -> Result<Node, JsValue> {
-        // console_log!("Creating node: {:?}", input);
-
+    fn create_node(&self, input: &Html<Msg>) -> Result<B::Node, Error> {
         match input {
             Html::Tag(HtmlTag {
                 tag,
@@ -223,53 +453,42 @@ where
                 children,
                 ..
             }) => {
-                let val: HtmlElement = self.document.create_element(&tag)?.dyn_into()?;
+                let node = self.backend.create_element(tag)?;
 
                 for attr in attrs {
-                    self.add_attribute(&val, attr)?;
+                    self.add_attribute(&node, attr)?;
                 }
 
-                let val: Node = val.into();
-
                 for child in children {
-                    let node = self.create_node(&child)?;
-                    val.append_child(&node)?;
+                    let child = self.create_node(child)?;
+                    self.backend.insert_before(&node, &child, None)?;
                 }
 
-                Ok(val)
-            }
-            Html::Text(text) => {
-                let val = self.document.create_text_node(&text);
-
-                Ok(val.into())
+                Ok(node)
             }
+            Html::Text(text) => self.backend.create_text(text),
         }
     }
 
-    fn remove_attribute(
-        &self,
-        node: &HtmlElement,
-        attribute: &Attribute<Msg>,
-    ) -> Result<(), JsValue> {
+    fn remove_attribute(&self, node: &B::Node, attribute: &Attribute<Msg>) -> Result<(), Error> {
         match attribute {
             Attribute::Key(_) => {}
-            // TODO: I think I know why elm normalizes before adding and removing attributes. We should probably do the same
             Attribute::Property(key, _) => {
-                Reflect::delete_property(node.as_ref(), &JsValue::from_str(&key))?;
+                self.backend.remove_property(node, key)?;
             }
             Attribute::Style(property, _) => {
-                node.style().remove_property(property)?;
+                self.backend.remove_style(node, property)?;
             }
+            Attribute::Event { type_, options, .. } if self.is_delegated(type_, options) => {}
             Attribute::Event {
-                type_, js_closure, ..
+                type_,
+                options,
+                listener,
+                ..
             } => {
-                let closure = js_closure.0.replace(None);
-
-                if let Some(closure) = closure {
-                    (node.as_ref() as &web_sys::EventTarget).remove_event_listener_with_callback(
-                        &type_,
-                        closure.as_ref().unchecked_ref(),
-                    )?;
+                if let Some(registered) = listener.take::<Registered<B::Listener, Msg>>() {
+                    self.backend
+                        .remove_listener(node, type_, options, registered.listener)?;
                 } else {
                     console_log!("WARN: Could not get a function to remove listener");
                 }
@@ -279,99 +498,458 @@ where
         Ok(())
     }
 
-    fn add_attribute(&self, node: &HtmlElement, attribute: &Attribute<Msg>) -> Result<(), JsValue> {
+    fn add_attribute(&self, node: &B::Node, attribute: &Attribute<Msg>) -> Result<(), Error> {
         match attribute {
             Attribute::Key(_) => {}
             Attribute::Property(key, value) => {
-                Reflect::set(
-                    node.as_ref(),
-                    &JsValue::from_str(&key),
-                    &property_value_to_json_value(value),
-                )?;
+                self.backend.set_property(node, key, value)?;
             }
             Attribute::Style(property, value) => {
-                node.style().set_property(property, value)?;
+                self.backend.set_style(node, property, value)?;
+            }
+            Attribute::Event { type_, options, .. } if self.is_delegated(type_, options) => {
+                if let (Some(delegation), Some((listened, _))) =
+                    (self.delegation, delegated_type(type_, options))
+                {
+                    delegation.listen(self.backend, listened)?;
+                }
             }
             Attribute::Event {
                 type_,
                 to_message,
-                stop_propagation,
-                prevent_default,
-                js_closure,
+                options,
+                listener,
             } => {
-                // console_log!("Adding event {}", type_);
-                // let name_for_logging = type_.clone();
-                let to_message = to_message.clone();
-                let program = self.program.clone();
-                let stop_propagation = *stop_propagation;
-                let prevent_default = *prevent_default;
-                let closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+                let to_message = Rc::new(RefCell::new(to_message.clone()));
+                let dispatcher = self.dispatcher.clone();
+                let EventOptions {
+                    prevent_default,
+                    stop_propagation,
+                    ..
+                } = *options;
+                let current = to_message.clone();
+                let handler = Rc::new(move |event: Event| {
                     if prevent_default {
                         event.prevent_default();
                     }
                     if stop_propagation {
                         event.stop_propagation();
                     }
-                    match &to_message {
-                        EventToMessage::StaticMsg(msg) => program.dispatch(msg),
-                        EventToMessage::Input(msg_fn) => program.dispatch(&msg_fn(
-                            event
-                                .target()
-                                .and_then(|target| {
-                                    target.dyn_into::<web_sys::HtmlInputElement>().ok()
-                                })
-                                .map(|el| el.value())
-                                .unwrap_or_default(),
-                        )),
-                        EventToMessage::InputWithClosure(closure) => program.dispatch(
-                            &closure.0.call_ish(
-                                event
-                                    .target()
-                                    .and_then(|target| {
-                                        target.dyn_into::<web_sys::HtmlInputElement>().ok()
-                                    })
-                                    .map(|el| el.value())
-                                    .unwrap_or_default(),
-                            ),
-                        ),
-                        EventToMessage::WithFilter { msg, filter } => {
-                            if filter(event) {
-                                program.dispatch(msg);
-                            }
-                        }
+                    // Rendering right away may update the handler, so it can not stay borrowed
+                    let (msg, is_input) = {
+                        let to_message = current.borrow();
+                        (to_message.to_message(&event), to_message.is_input())
                     };
-                    // console_log!("On Event {}!", name_for_logging);
-                }) as Box<Fn(_)>);
+                    if let Some(msg) = msg {
+                        if is_input {
+                            dispatcher.dispatch_immediately(msg);
+                        } else {
+                            dispatcher.dispatch(msg);
+                        }
+                    }
+                });
 
-                (node.as_ref() as &web_sys::EventTarget)
-                    .add_event_listener_with_callback(&type_, closure.as_ref().unchecked_ref())?;
+                // Keep the listener with the attribute, so that it can be removed again
+                listener.set(Registered {
+                    listener: self.backend.add_listener(node, type_, options, handler)?,
+                    to_message,
+                });
+            }
+        }
 
-                // Save the closure somewhere safe so that it will not be freed and invalidated
+        Ok(())
+    }
+}
 
-                let ret = js_closure.0.replace(Some(closure));
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{hydrate, render};
+    use crate::attributes::{class, key, style};
+    use crate::backend::mock::{MockBackend, Operation};
+    use crate::cmd::Dispatcher;
+    use crate::dom_event::EventData;
+    use crate::events::on_click;
+    use crate::html::{div, li, p, text, ul, Html};
+
+    fn list(items: &[&str]) -> Html<String> {
+        ul(
+            &[],
+            &items
+                .iter()
+                .map(|item| li(&[key(item.to_string())], &[text(item)]))
+                .collect::<Vec<_>>(),
+        )
+    }
 
-                if ret.is_some() {
-                    console_log!("to_message did already have a closure???");
-                }
+    #[test]
+    fn renders_and_patches_through_the_backend() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let dispatcher = Dispatcher::new(|_: String| {});
+
+        let mut first: Html<String> = ul(&[class("todos")], &[li(&[], &[text("a")])]);
+        render(&backend, &dispatcher, &root, &mut first, None).unwrap();
+        assert_eq!(
+            backend.inner_html(root),
+            "<ul className=\"todos\"><li>a</li></ul>"
+        );
+
+        backend.clear_operations();
+        let mut second: Html<String> = ul(&[class("done")], &[li(&[], &[text("b")])]);
+        render(&backend, &dispatcher, &root, &mut second, Some(&first)).unwrap();
+        assert_eq!(
+            backend.inner_html(root),
+            "<ul className=\"done\"><li>b</li></ul>"
+        );
+        assert!(!backend
+            .operations()
+            .iter()
+            .any(|operation| matches!(operation, Operation::CreateElement(..))));
+    }
 
-                // closure.forget();
-            }
+    #[test]
+    fn moves_keyed_children() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let dispatcher = Dispatcher::new(|_: String| {});
+
+        let mut first = list(&["a", "b", "c", "d"]);
+        render(&backend, &dispatcher, &root, &mut first, None).unwrap();
+        backend.clear_operations();
+
+        let mut second = list(&["d", "a", "b", "c"]);
+        render(&backend, &dispatcher, &root, &mut second, Some(&first)).unwrap();
+
+        assert_eq!(
+            backend.inner_html(root),
+            "<ul><li>d</li><li>a</li><li>b</li><li>c</li></ul>"
+        );
+        // Only the last item is moved, nothing is created or rewritten
+        assert_eq!(backend.operations().len(), 1);
+    }
+
+    #[test]
+    fn keyed_children_end_up_in_the_new_order() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["a", "b", "c"], &["c", "x", "a"]),
+            (&["a", "b", "c", "d", "e"], &["e", "b", "d", "a", "c"]),
+            (&["a", "b"], &["x", "b", "y", "a", "z"]),
+            (&["a", "b", "c", "d"], &["d"]),
+        ];
+
+        for (old, new) in cases {
+            let backend = MockBackend::new();
+            let root = backend.root();
+            let dispatcher = Dispatcher::new(|_: String| {});
+
+            let mut old = list(old);
+            let mut new_html = list(new);
+            render(&backend, &dispatcher, &root, &mut old, None).unwrap();
+            render(&backend, &dispatcher, &root, &mut new_html, Some(&old)).unwrap();
+
+            let items = new
+                .iter()
+                .map(|item| format!("<li>{}</li>", item))
+                .collect::<String>();
+            assert_eq!(backend.inner_html(root), format!("<ul>{}</ul>", items));
         }
+    }
 
-        Ok(())
+    #[test]
+    fn listeners_dispatch_messages_and_survive_rerenders() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let messages = Rc::new(RefCell::new(vec![]));
+        let dispatcher = {
+            let messages = messages.clone();
+            Dispatcher::new(move |msg: String| messages.borrow_mut().push(msg))
+        };
+
+        let mut first: Html<String> = ul(&[on_click("clicked".to_owned())], &[]);
+        render(&backend, &dispatcher, &root, &mut first, None).unwrap();
+        let mut second: Html<String> = ul(&[on_click("clicked".to_owned())], &[]);
+        render(&backend, &dispatcher, &root, &mut second, Some(&first)).unwrap();
+        drop(first);
+
+        let node = backend.children(root)[0];
+        assert_eq!(backend.listener_count(node), 1);
+        backend.dispatch_event(
+            node,
+            EventData {
+                type_: "click".to_owned(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(*messages.borrow(), vec!["clicked".to_owned()]);
     }
 
-    fn remove_to_remove(&self) -> Result<(), JsValue> {
-        for (parent, child) in &self.to_remove {
-            parent.remove_child(&child)?;
+    #[test]
+    fn an_event_given_twice_keeps_a_listener_for_each() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let messages = Rc::new(RefCell::new(vec![]));
+        let dispatcher = {
+            let messages = messages.clone();
+            Dispatcher::new(move |msg: String| messages.borrow_mut().push(msg))
+        };
+        let view = || {
+            let click = on_click("clicked".to_owned());
+            ul(&[click.clone(), click], &[])
+        };
+
+        let mut first: Html<String> = view();
+        render(&backend, &dispatcher, &root, &mut first, None).unwrap();
+        let mut second: Html<String> = view();
+        render(&backend, &dispatcher, &root, &mut second, Some(&first)).unwrap();
+        drop(first);
+
+        let node = backend.children(root)[0];
+        assert_eq!(backend.listener_count(node), 2);
+        backend.dispatch_event(
+            node,
+            EventData {
+                type_: "click".to_owned(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(*messages.borrow(), vec!["clicked", "clicked"]);
+    }
+
+    #[test]
+    fn cloned_subtrees_keep_a_listener_each() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let messages = Rc::new(RefCell::new(vec![]));
+        let dispatcher = {
+            let messages = messages.clone();
+            Dispatcher::new(move |msg: String| messages.borrow_mut().push(msg))
+        };
+        let item: Html<String> = li(&[on_click("clicked".to_owned())], &[]);
+
+        let mut first = ul(&[], &[item.clone(), item]);
+        render(&backend, &dispatcher, &root, &mut first, None).unwrap();
+        let items = backend.children(backend.children(root)[0]);
+        for item in &items {
+            backend.dispatch_event(
+                *item,
+                EventData {
+                    type_: "click".to_owned(),
+                    ..Default::default()
+                },
+            );
         }
-        Ok(())
+        assert_eq!(*messages.borrow(), vec!["clicked", "clicked"]);
+
+        // Removing the listener of the first item leaves the second one alone
+        let mut second = ul(
+            &[],
+            &[li(&[], &[]), li(&[on_click("clicked".to_owned())], &[])],
+        );
+        render(&backend, &dispatcher, &root, &mut second, Some(&first)).unwrap();
+        assert_eq!(backend.listener_count(items[0]), 0);
+        assert_eq!(backend.listener_count(items[1]), 1);
+    }
+
+    #[test]
+    fn changed_messages_reuse_the_listener() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let messages = Rc::new(RefCell::new(vec![]));
+        let dispatcher = {
+            let messages = messages.clone();
+            Dispatcher::new(move |msg: String| messages.borrow_mut().push(msg))
+        };
+        let click = EventData {
+            type_: "click".to_owned(),
+            ..Default::default()
+        };
+
+        let mut first: Html<String> = ul(&[on_click("first".to_owned())], &[]);
+        render(&backend, &dispatcher, &root, &mut first, None).unwrap();
+        backend.clear_operations();
+        let mut second: Html<String> = ul(&[on_click("second".to_owned())], &[]);
+        render(&backend, &dispatcher, &root, &mut second, Some(&first)).unwrap();
+        assert!(backend.operations().is_empty());
+
+        let node = backend.children(root)[0];
+        backend.dispatch_event(node, click.clone());
+        assert_eq!(*messages.borrow(), vec!["second".to_owned()]);
+
+        let mut third: Html<String> = ul(&[], &[]);
+        render(&backend, &dispatcher, &root, &mut third, Some(&second)).unwrap();
+        assert_eq!(
+            backend.operations(),
+            vec![Operation::RemoveListener(node, "click".to_owned())]
+        );
+        assert_eq!(backend.listener_count(node), 0);
+    }
+
+    // Put `markup` into the root, like a page rendered on the server
+    fn markup(backend: &MockBackend, mut markup: Html<String>) {
+        render(
+            backend,
+            &Dispatcher::new(|_| {}),
+            &backend.root(),
+            &mut markup,
+            None,
+        )
+        .unwrap();
+        backend.clear_operations();
+    }
+
+    fn todos(done: &str) -> Html<String> {
+        ul(
+            &[class("todos")],
+            &[
+                li(&[on_click("first".to_owned())], &[text("Milk")]),
+                li(&[class(done)], &[text("Eggs"), text(" left")]),
+            ],
+        )
+    }
+
+    #[test]
+    fn hydrating_matching_markup_only_adds_listeners() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        let messages = Rc::new(RefCell::new(vec![]));
+        let dispatcher = {
+            let messages = messages.clone();
+            Dispatcher::new(move |msg: String| messages.borrow_mut().push(msg))
+        };
+        markup(
+            &backend,
+            ul(
+                &[class("todos")],
+                &[
+                    text("\n  "),
+                    li(&[], &[text("Milk")]),
+                    text("\n  "),
+                    li(&[class("done")], &[text("Eggs"), text(" left")]),
+                    text("\n"),
+                ],
+            ),
+        );
+        let list = backend.children(root)[0];
+        let nodes = backend.children(list);
+
+        let mismatches = hydrate(&backend, &dispatcher, &root, &mut todos("done")).unwrap();
+
+        assert_eq!(mismatches, Vec::<String>::new());
+        // Only the whitespace between the tags is removed
+        assert_eq!(
+            backend.operations(),
+            vec![
+                Operation::RemoveChild {
+                    parent: list,
+                    child: nodes[0],
+                },
+                Operation::AddListener(nodes[1], "click".to_owned(), Default::default()),
+                Operation::RemoveChild {
+                    parent: list,
+                    child: nodes[2],
+                },
+                Operation::RemoveChild {
+                    parent: list,
+                    child: nodes[4],
+                },
+            ]
+        );
+        let first = nodes[1];
+        backend.dispatch_event(
+            first,
+            EventData {
+                type_: "click".to_owned(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(*messages.borrow(), vec!["first".to_owned()]);
+    }
+
+    #[test]
+    fn hydrating_patches_what_does_not_match() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        markup(
+            &backend,
+            ul(
+                &[class("todos"), style("color", "red")],
+                &[
+                    p(&[], &[text("Milk")]),
+                    li(&[class("todo")], &[text("Eggs"), text(" gone")]),
+                ],
+            ),
+        );
+        let list = backend.children(root)[0];
+        let nodes = backend.children(list);
+        let eggs = backend.children(nodes[1]);
+
+        let mismatches = hydrate(
+            &backend,
+            &Dispatcher::new(|_| {}),
+            &root,
+            &mut todos("done"),
+        )
+        .unwrap();
+
+        assert_eq!(mismatches.len(), 4);
+        assert_eq!(
+            backend.inner_html(root),
+            r#"<ul className="todos"><li>Milk</li><li className="done">Eggs left</li></ul>"#
+        );
+        let operations = backend.operations();
+        assert!(operations.contains(&Operation::RemoveStyle(list, "color".to_owned())));
+        assert!(operations.contains(&Operation::SetText(eggs[1], " left".to_owned())));
+        assert!(operations.contains(&Operation::SetProperty(
+            nodes[1],
+            "className".to_owned(),
+            "done".into()
+        )));
+        assert!(!operations.contains(&Operation::SetProperty(
+            list,
+            "className".to_owned(),
+            "todos".into()
+        )));
     }
-}
 
-fn property_value_to_json_value(val: &PropertyValue) -> JsValue {
-    match val {
-        PropertyValue::String(ref value) => JsValue::from_str(value),
-        PropertyValue::Bool(value) => JsValue::from_bool(*value),
+    #[test]
+    fn hydrating_removes_extra_children_but_leaves_the_siblings_of_the_view() {
+        let backend = MockBackend::new();
+        let root = backend.root();
+        markup(
+            &backend,
+            div(
+                &[],
+                &[
+                    ul(
+                        &[class("todos")],
+                        &[
+                            li(&[], &[text("Milk")]),
+                            li(&[class("done")], &[text("Eggs"), text(" left")]),
+                            li(&[], &[text("Bread")]),
+                        ],
+                    ),
+                    p(&[], &[text("A script")]),
+                ],
+            ),
+        );
+        // The markup of the view is usually followed by scripts in the body
+        let body = backend.children(root)[0];
+
+        let mismatches = hydrate(
+            &backend,
+            &Dispatcher::new(|_| {}),
+            &body,
+            &mut todos("done"),
+        )
+        .unwrap();
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(
+            backend.inner_html(body),
+            r#"<ul className="todos"><li>Milk</li><li className="done">Eggs left</li></ul><p>A script</p>"#
+        );
     }
 }
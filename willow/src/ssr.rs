@@ -0,0 +1,155 @@
+//! Render `Html` to an HTML string, to prerender pages on a server or at build time. Events and
+//! keys only matter in the browser and are left out.
+
+use crate::{
+    attributes::attribute_name,
+    html::{Attribute, Html, HtmlTag, PropertyValue},
+};
+
+/// Elements that can not have children, and are written without a closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+pub fn render_to_string<Msg>(html: &Html<Msg>) -> String {
+    let mut out = String::new();
+    write_html(&mut out, html);
+    out
+}
+
+fn write_html<Msg>(out: &mut String, html: &Html<Msg>) {
+    match html {
+        Html::Text(text) => out.push_str(&escape(text)),
+        Html::Tag(tag) => write_tag(out, tag),
+    }
+}
+
+fn write_tag<Msg>(out: &mut String, tag: &HtmlTag<Msg>) {
+    out.push('<');
+    out.push_str(&tag.tag);
+
+    let mut styles = vec![];
+    for attr in &tag.attrs {
+        match attr {
+            Attribute::Property(name, PropertyValue::String(value)) => {
+                out.push_str(&format!(" {}=\"{}\"", attribute_name(name), escape(value)));
+            }
+            Attribute::Property(name, PropertyValue::Bool(value)) => {
+                // Boolean attributes are true when present, whatever the value is
+                if *value {
+                    out.push_str(&format!(" {}", attribute_name(name)));
+                }
+            }
+            Attribute::Style(property, value) => styles.push(format!("{}: {}", property, value)),
+            Attribute::Event { .. } | Attribute::Key(_) => {}
+        }
+    }
+    if !styles.is_empty() {
+        out.push_str(&format!(" style=\"{}\"", escape(&styles.join("; "))));
+    }
+    out.push('>');
+
+    if VOID_ELEMENTS.contains(&tag.tag.as_str()) {
+        return;
+    }
+
+    let mut after_text = false;
+    for child in &tag.children {
+        let is_text = match child {
+            Html::Text(_) => true,
+            Html::Tag(_) => false,
+        };
+        // The parser joins adjacent text into one node, so a comment keeps them apart
+        if after_text && is_text {
+            out.push_str("<!-- -->");
+        }
+        after_text = is_text;
+        write_html(out, child);
+    }
+    out.push_str(&format!("</{}>", tag.tag));
+}
+
+/// Escape text so it can be used both as text content and as a quoted attribute value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_to_string;
+    use crate::attributes::{checked, class, for_, hidden, key, style, type_, value};
+    use crate::events::on_click;
+    use crate::html::{div, input, label, text, Html};
+
+    #[test]
+    fn renders_attributes_and_styles() {
+        let html: Html<()> = div(
+            &[
+                class("todo"),
+                key("1".to_owned()),
+                style("visibility", "hidden"),
+                style("color", "red"),
+                on_click(()),
+            ],
+            &[label(&[for_("toggle")], &[text("Toggle")])],
+        );
+
+        assert_eq!(
+            render_to_string(&html),
+            "<div class=\"todo\" style=\"visibility: hidden; color: red\">\
+             <label for=\"toggle\">Toggle</label></div>"
+        );
+    }
+
+    #[test]
+    fn renders_void_elements_and_boolean_attributes() {
+        let html: Html<()> = div(
+            &[],
+            &[
+                input(&[type_("checkbox"), checked(true), hidden(false)], &[]),
+                div(&[], &[]),
+            ],
+        );
+
+        assert_eq!(
+            render_to_string(&html),
+            "<div><input type=\"checkbox\" checked><div></div></div>"
+        );
+    }
+
+    #[test]
+    fn escapes_text_and_attribute_values() {
+        let html: Html<()> = div(
+            &[value("\"quoted\" & <b>")],
+            &[text("<script>alert('hi')</script>")],
+        );
+
+        assert_eq!(
+            render_to_string(&html),
+            "<div value=\"&quot;quoted&quot; &amp; &lt;b&gt;\">\
+             &lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;</div>"
+        );
+    }
+
+    #[test]
+    fn separates_adjacent_text() {
+        let html: Html<()> = div(&[], &[text("1"), text(" left"), label(&[], &[]), text("!")]);
+
+        assert_eq!(
+            render_to_string(&html),
+            "<div>1<!-- --> left<label></label>!</div>"
+        );
+    }
+}
@@ -0,0 +1,591 @@
+//! Keep values in `localStorage` or `sessionStorage`, as JSON.
+//!
+//! `save`, `load` and `remove` are commands. `get` reads a value right away, for the initial
+//! model of a program. Tests plug in a `MemoryStore` with `set_store`.
+//!
+//! Values whose type changes over time, like the model of an app, can be kept with `Versioned`
+//! instead. It stores a version next to the value, and migrates old values when they are read.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::ptr::fn_addr_eq;
+use std::rc::Rc;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    cmd::{Dispatcher, Effect},
+    Cmd, Error,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Area {
+    /// `localStorage`, kept until it is removed
+    Local,
+    /// `sessionStorage`, kept until the tab is closed
+    Session,
+}
+
+/// Where the values of an `Area` are kept
+pub trait Store {
+    fn get_item(&self, key: &str) -> Result<Option<String>, Error>;
+    fn set_item(&self, key: &str, value: &str) -> Result<(), Error>;
+    fn remove_item(&self, key: &str) -> Result<(), Error>;
+}
+
+thread_local! {
+    static STORES: RefCell<Vec<(Area, Rc<Store>)>> = RefCell::new(vec![]);
+}
+
+/// Keep the values of `area` on this thread in `store`
+pub fn set_store<S: Store + 'static>(area: Area, store: Rc<S>) {
+    STORES.with(|stores| {
+        let mut stores = stores.borrow_mut();
+        stores.retain(|(existing, _)| *existing != area);
+        stores.push((area, store));
+    });
+}
+
+fn store(area: Area) -> Result<Rc<Store>, Error> {
+    let store = STORES.with(|stores| {
+        stores
+            .borrow()
+            .iter()
+            .find(|(existing, _)| *existing == area)
+            .map(|(_, store)| store.clone())
+    });
+    match store {
+        Some(store) => Ok(store),
+        #[cfg(feature = "web")]
+        None => Ok(Rc::new(web::WebStorage(area))),
+        #[cfg(not(feature = "web"))]
+        None => Err(Error::from("No storage, see `storage::set_store`")),
+    }
+}
+
+/// Read the value at `key` right away. It is `None` if nothing is stored there.
+pub fn get<T: DeserializeOwned>(area: Area, key: &str) -> Result<Option<T>, Error> {
+    match store(area)?.get_item(key)? {
+        Some(data) => serde_json::from_str(&data)
+            .map(Some)
+            .map_err(|err| Error::from(format!("Could not read {:?}: {}", key, err))),
+        None => Ok(None),
+    }
+}
+
+/// Write `value` to `key`
+pub fn save<T: Serialize, Msg: Clone + 'static>(area: Area, key: &str, value: &T) -> Cmd<Msg> {
+    Cmd::effect(Save::new(area, key, value))
+}
+
+/// Read the value at `key`, and dispatch the message for it
+pub fn load<T, Msg>(
+    area: Area,
+    key: &str,
+    to_message: fn(Result<Option<T>, Error>) -> Msg,
+) -> Cmd<Msg>
+where
+    T: DeserializeOwned + 'static,
+    Msg: Clone + 'static,
+{
+    Cmd::effect(Load {
+        area,
+        key: key.to_owned(),
+        to_message,
+    })
+}
+
+pub fn remove<Msg: Clone + 'static>(area: Area, key: &str) -> Cmd<Msg> {
+    Cmd::effect(Remove {
+        area,
+        key: key.to_owned(),
+    })
+}
+
+/// The effect of `save`. Use it directly to handle errors with `Effect::on_error`.
+#[derive(Debug, PartialEq)]
+pub struct Save {
+    area: Area,
+    key: String,
+    // Serialized right away, so that the value does not have to be kept around
+    data: Result<String, Error>,
+}
+
+impl Save {
+    pub fn new<T: Serialize>(area: Area, key: &str, value: &T) -> Self {
+        Save {
+            area,
+            key: key.to_owned(),
+            data: serde_json::to_string(value)
+                .map_err(|err| Error::from(format!("Could not write {:?}: {}", key, err))),
+        }
+    }
+}
+
+impl<Msg> Effect<Msg> for Save {
+    fn run(&self, _: &Dispatcher<Msg>) -> Result<(), Error> {
+        let data = self.data.clone()?;
+        store(self.area)?.set_item(&self.key, &data)
+    }
+}
+
+pub struct Load<T, Msg> {
+    area: Area,
+    key: String,
+    to_message: fn(Result<Option<T>, Error>) -> Msg,
+}
+
+impl<T: DeserializeOwned, Msg> Effect<Msg> for Load<T, Msg> {
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error> {
+        dispatcher.dispatch((self.to_message)(get(self.area, &self.key)));
+        Ok(())
+    }
+}
+
+impl<T, Msg> PartialEq for Load<T, Msg> {
+    fn eq(&self, other: &Self) -> bool {
+        self.area == other.area
+            && self.key == other.key
+            && fn_addr_eq(self.to_message, other.to_message)
+    }
+}
+
+impl<T, Msg> Debug for Load<T, Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Load")
+            .field("area", &self.area)
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Remove {
+    area: Area,
+    key: String,
+}
+
+impl<Msg> Effect<Msg> for Remove {
+    fn run(&self, _: &Dispatcher<Msg>) -> Result<(), Error> {
+        store(self.area)?.remove_item(&self.key)
+    }
+}
+
+/// A value kept at `key` together with its version, which is the number of migrations. Each
+/// migration turns the JSON of one version into the next, and they are run in order on values
+/// written by older versions. Values saved without a version, like with `save`, are version 0.
+/// The value is stored as `{"willow_version": 2, "data": ...}`.
+///
+/// ```ignore
+/// let model = Versioned::<Model>::new(Area::Local, "todos")
+///     .migration(add_visibility)
+///     .migration(rename_entries);
+/// ```
+pub struct Versioned<T> {
+    area: Area,
+    key: String,
+    migrations: Vec<fn(Value) -> Result<Value, Error>>,
+    value: PhantomData<fn() -> T>,
+}
+
+// How a versioned value is stored. The tag is one apps are unlikely to use themselves, so that
+// values saved without a version are not mistaken for this.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Stored<D> {
+    #[serde(rename = "willow_version")]
+    version: usize,
+    data: D,
+}
+
+/// Why a versioned value could not be read
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadError {
+    /// The store could not be read at all
+    Storage(Error),
+    /// The stored data can not be turned into the current version. It is left in the store,
+    /// and given here, so that it is not lost.
+    Unreadable {
+        version: usize,
+        data: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Storage(err) => write!(f, "{}", err),
+            LoadError::Unreadable {
+                version, reason, ..
+            } => write!(f, "Could not read data of version {}: {}", version, reason),
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + 'static> Versioned<T> {
+    pub fn new(area: Area, key: &str) -> Self {
+        Versioned {
+            area,
+            key: key.to_owned(),
+            migrations: vec![],
+            value: PhantomData,
+        }
+    }
+
+    /// Add a migration from the current version to the next
+    pub fn migration(mut self, migrate: fn(Value) -> Result<Value, Error>) -> Self {
+        self.migrations.push(migrate);
+        self
+    }
+
+    pub fn version(&self) -> usize {
+        self.migrations.len()
+    }
+
+    pub fn save<Msg: Clone + 'static>(&self, value: &T) -> Cmd<Msg> {
+        let stored = Stored {
+            version: self.version(),
+            data: value,
+        };
+        Cmd::effect(Save::new(self.area, &self.key, &stored))
+    }
+
+    /// Read and migrate the value right away. It is `None` if nothing is stored.
+    pub fn get(&self) -> Result<Option<T>, LoadError> {
+        let data = store(self.area)
+            .and_then(|store| store.get_item(&self.key))
+            .map_err(LoadError::Storage)?;
+        match data {
+            Some(data) => self.decode(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read and migrate the value, and dispatch the message for it
+    pub fn load<Msg: Clone + 'static>(
+        &self,
+        to_message: fn(Result<Option<T>, LoadError>) -> Msg,
+    ) -> Cmd<Msg> {
+        Cmd::effect(LoadVersioned {
+            versioned: self.clone(),
+            to_message,
+        })
+    }
+
+    fn decode(&self, data: &str) -> Result<T, LoadError> {
+        let unreadable = |version, reason: String| LoadError::Unreadable {
+            version,
+            data: data.to_owned(),
+            reason,
+        };
+
+        let json: Value =
+            serde_json::from_str(data).map_err(|err| unreadable(0, err.to_string()))?;
+        let Stored { version, data } = match serde_json::from_value(json.clone()) {
+            Ok(stored) => stored,
+            Err(_) => Stored {
+                version: 0,
+                data: json,
+            },
+        };
+        if version > self.version() {
+            return Err(unreadable(
+                version,
+                format!("it is newer than version {}", self.version()),
+            ));
+        }
+
+        let mut json = data;
+        for (from, migrate) in self.migrations.iter().enumerate().skip(version) {
+            json = migrate(json).map_err(|err| {
+                unreadable(version, format!("migrating from version {}: {}", from, err))
+            })?;
+        }
+        serde_json::from_value(json).map_err(|err| unreadable(version, err.to_string()))
+    }
+}
+
+impl<T> Clone for Versioned<T> {
+    fn clone(&self) -> Self {
+        Versioned {
+            area: self.area,
+            key: self.key.clone(),
+            migrations: self.migrations.clone(),
+            value: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Versioned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.area == other.area
+            && self.key == other.key
+            && self.migrations.len() == other.migrations.len()
+            && self
+                .migrations
+                .iter()
+                .zip(&other.migrations)
+                .all(|(a, b)| fn_addr_eq(*a, *b))
+    }
+}
+
+impl<T> Debug for Versioned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Versioned")
+            .field("area", &self.area)
+            .field("key", &self.key)
+            .field("version", &self.migrations.len())
+            .finish()
+    }
+}
+
+/// The effect of `Versioned::load`
+pub struct LoadVersioned<T, Msg> {
+    versioned: Versioned<T>,
+    to_message: fn(Result<Option<T>, LoadError>) -> Msg,
+}
+
+impl<T, Msg> Effect<Msg> for LoadVersioned<T, Msg>
+where
+    T: Serialize + DeserializeOwned + 'static,
+{
+    fn run(&self, dispatcher: &Dispatcher<Msg>) -> Result<(), Error> {
+        dispatcher.dispatch((self.to_message)(self.versioned.get()));
+        Ok(())
+    }
+}
+
+impl<T, Msg> PartialEq for LoadVersioned<T, Msg> {
+    fn eq(&self, other: &Self) -> bool {
+        self.versioned == other.versioned && fn_addr_eq(self.to_message, other.to_message)
+    }
+}
+
+impl<T, Msg> Debug for LoadVersioned<T, Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LoadVersioned")
+            .field(&self.versioned)
+            .finish()
+    }
+}
+
+/// A store in memory, for tests
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    items: RefCell<BTreeMap<String, String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Every key with its value, sorted by key
+    pub fn items(&self) -> Vec<(String, String)> {
+        self.items
+            .borrow()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+impl Store for MemoryStore {
+    fn get_item(&self, key: &str) -> Result<Option<String>, Error> {
+        Ok(self.items.borrow().get(key).cloned())
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<(), Error> {
+        self.items
+            .borrow_mut()
+            .insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    fn remove_item(&self, key: &str) -> Result<(), Error> {
+        self.items.borrow_mut().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "web")]
+pub use self::web::WebStorage;
+
+#[cfg(feature = "web")]
+mod web {
+    use super::{Area, Store};
+    use crate::Error;
+
+    /// The storage of the browser
+    pub struct WebStorage(pub Area);
+
+    impl WebStorage {
+        fn storage(&self) -> Result<web_sys::Storage, Error> {
+            let window =
+                web_sys::window().ok_or_else(|| Error::from("no global `window` exists"))?;
+            let storage = match self.0 {
+                Area::Local => window.local_storage()?,
+                Area::Session => window.session_storage()?,
+            };
+            storage.ok_or_else(|| Error::from(format!("{:?} storage is not available", self.0)))
+        }
+    }
+
+    impl Store for WebStorage {
+        fn get_item(&self, key: &str) -> Result<Option<String>, Error> {
+            Ok(self.storage()?.get_item(key)?)
+        }
+
+        fn set_item(&self, key: &str, value: &str) -> Result<(), Error> {
+            Ok(self.storage()?.set_item(key, value)?)
+        }
+
+        fn remove_item(&self, key: &str) -> Result<(), Error> {
+            Ok(self.storage()?.remove_item(key)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use serde_derive::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use super::{
+        get, load, remove, save, set_store, Area, LoadError, MemoryStore, Store, Versioned,
+    };
+    use crate::{Dispatcher, Error};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Loaded(Result<Option<Vec<String>>, Error>),
+    }
+
+    #[test]
+    fn saves_loads_and_removes_values() {
+        let local = Rc::new(MemoryStore::new());
+        set_store(Area::Local, local.clone());
+        set_store(Area::Session, Rc::new(MemoryStore::new()));
+
+        let messages = Rc::new(std::cell::RefCell::new(vec![]));
+        let sent = messages.clone();
+        let dispatcher = Dispatcher::new(move |message| sent.borrow_mut().push(message));
+
+        let todos = vec!["Buy milk".to_owned()];
+        save(Area::Local, "todos", &todos).run(&dispatcher).unwrap();
+        assert_eq!(
+            local.items(),
+            vec![("todos".to_owned(), r#"["Buy milk"]"#.to_owned())]
+        );
+        assert_eq!(get(Area::Local, "todos"), Ok(Some(todos.clone())));
+        assert_eq!(get::<Vec<String>>(Area::Session, "todos"), Ok(None));
+
+        let cmd = load(Area::Local, "todos", Msg::Loaded);
+        assert_eq!(cmd, load(Area::Local, "todos", Msg::Loaded));
+        cmd.run(&dispatcher).unwrap();
+
+        local.set_item("todos", "not json").unwrap();
+        cmd.run(&dispatcher).unwrap();
+
+        remove::<Msg>(Area::Local, "todos")
+            .run(&dispatcher)
+            .unwrap();
+        cmd.run(&dispatcher).unwrap();
+
+        let messages = messages.borrow();
+        assert_eq!(messages[0], Msg::Loaded(Ok(Some(todos))));
+        match &messages[1] {
+            Msg::Loaded(Err(_)) => {}
+            message => panic!("Expected an error, got {:?}", message),
+        }
+        assert_eq!(messages[2], Msg::Loaded(Ok(None)));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Model {
+        todos: Vec<String>,
+        visibility: String,
+    }
+
+    // Version 0 was a list of strings
+    fn wrap_in_model(todos: Value) -> Result<Value, Error> {
+        Ok(json!({ "todos": todos }))
+    }
+
+    fn add_visibility(mut model: Value) -> Result<Value, Error> {
+        model["visibility"] = json!("All");
+        Ok(model)
+    }
+
+    #[test]
+    fn migrates_versioned_values() {
+        let local = Rc::new(MemoryStore::new());
+        set_store(Area::Local, local.clone());
+
+        let model = Versioned::<Model>::new(Area::Local, "model")
+            .migration(wrap_in_model)
+            .migration(add_visibility);
+        assert_eq!(model.get(), Ok(None));
+
+        local.set_item("model", r#"["Buy milk"]"#).unwrap();
+        let expected = Model {
+            todos: vec!["Buy milk".to_owned()],
+            visibility: "All".to_owned(),
+        };
+        assert_eq!(model.get(), Ok(Some(expected)));
+
+        local
+            .set_item("model", r#"{"willow_version":1,"data":{"todos":[]}}"#)
+            .unwrap();
+        assert_eq!(model.get().unwrap().unwrap().todos, Vec::<String>::new());
+
+        let saved = Model {
+            todos: vec![],
+            visibility: "Active".to_owned(),
+        };
+        model
+            .save::<()>(&saved)
+            .run(&Dispatcher::new(|_| ()))
+            .unwrap();
+        assert_eq!(
+            local.get_item("model"),
+            Ok(Some(
+                r#"{"willow_version":2,"data":{"todos":[],"visibility":"Active"}}"#.to_owned()
+            ))
+        );
+
+        local
+            .set_item("model", r#"{"willow_version":3,"data":{}}"#)
+            .unwrap();
+        match model.get() {
+            Err(LoadError::Unreadable { version, data, .. }) => {
+                assert_eq!(version, 3);
+                assert_eq!(data, r#"{"willow_version":3,"data":{}}"#);
+            }
+            result => panic!("Expected the data to be unreadable, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn values_saved_without_a_version_are_version_0_whatever_their_fields() {
+        let local = Rc::new(MemoryStore::new());
+        set_store(Area::Local, local.clone());
+        let stored = Versioned::<Value>::new(Area::Local, "stored").migration(add_visibility);
+
+        local
+            .set_item("stored", r#"{"version":1,"data":"Buy milk"}"#)
+            .unwrap();
+        assert_eq!(
+            stored.get(),
+            Ok(Some(
+                json!({"version": 1, "data": "Buy milk", "visibility": "All"})
+            ))
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use std::ptr::fn_addr_eq;
+
+/// Long-lived event sources. `Program` asks for the subscriptions after every message and
+/// starts or stops the underlying listeners so they match what was returned.
+#[derive(Clone, Debug)]
+pub enum Sub<Msg> {
+    None,
+    Batch(Vec<Sub<Msg>>),
+    // Send `msg` every `interval` milliseconds
+    Every { interval: i32, msg: Msg },
+    // Window-level keydown, gives the `key` of the event
+    KeyDown(fn(String) -> Msg),
+    // Window resize, gives the new inner width and height
+    Resize(fn(i32, i32) -> Msg),
+    // History navigation, gives the new location href
+    PopState(fn(String) -> Msg),
+}
+
+impl<Msg: Clone> Sub<Msg> {
+    pub fn none() -> Self {
+        Sub::None
+    }
+
+    pub fn batch(subs: Vec<Sub<Msg>>) -> Self {
+        Sub::Batch(subs)
+    }
+
+    pub fn every(interval: i32, msg: Msg) -> Self {
+        Sub::Every { interval, msg }
+    }
+
+    pub fn on_key_down(to_message: fn(String) -> Msg) -> Self {
+        Sub::KeyDown(to_message)
+    }
+
+    pub fn on_resize(to_message: fn(i32, i32) -> Msg) -> Self {
+        Sub::Resize(to_message)
+    }
+
+    pub fn on_pop_state(to_message: fn(String) -> Msg) -> Self {
+        Sub::PopState(to_message)
+    }
+
+    /// All the event sources in this subscription, without `None` and `Batch`
+    pub fn sources(&self) -> Vec<Sub<Msg>> {
+        match self {
+            Sub::None => vec![],
+            Sub::Batch(subs) => subs.iter().flat_map(Sub::sources).collect(),
+            source => vec![source.clone()],
+        }
+    }
+}
+
+impl<Msg: PartialEq> PartialEq for Sub<Msg> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Sub::None, Sub::None) => true,
+            (Sub::Batch(a), Sub::Batch(b)) => a == b,
+            (
+                Sub::Every { interval, msg },
+                Sub::Every {
+                    interval: other_interval,
+                    msg: other_msg,
+                },
+            ) => interval == other_interval && msg == other_msg,
+            (Sub::KeyDown(a), Sub::KeyDown(b)) => fn_addr_eq(*a, *b),
+            (Sub::Resize(a), Sub::Resize(b)) => fn_addr_eq(*a, *b),
+            (Sub::PopState(a), Sub::PopState(b)) => fn_addr_eq(*a, *b),
+            _ => false,
+        }
+    }
+}
+
+/// What has to change to go from the `active` event sources to the ones in a subscription
+#[derive(Debug, PartialEq)]
+pub struct Changes<Msg> {
+    /// The indices in `active` of the sources that are gone
+    pub remove: Vec<usize>,
+    /// The sources that are not active yet, each once
+    pub add: Vec<Sub<Msg>>,
+}
+
+/// Diff the event sources of `subs` against the `active` ones. Sources that are in both are kept
+/// as they are.
+pub fn changes<Msg: PartialEq + Clone>(active: &[Sub<Msg>], subs: &Sub<Msg>) -> Changes<Msg> {
+    let sources = subs.sources();
+    let remove = active
+        .iter()
+        .enumerate()
+        .filter(|(_, sub)| !sources.contains(sub))
+        .map(|(index, _)| index)
+        .collect();
+
+    let mut add = vec![];
+    for source in sources {
+        if !active.contains(&source) && !add.contains(&source) {
+            add.push(source);
+        }
+    }
+    Changes { remove, add }
+}
+
+#[cfg(feature = "web")]
+pub(crate) use self::web::Subscriptions;
+
+/// The listeners behind the subscriptions, which only exist in the browser
+#[cfg(feature = "web")]
+mod web {
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen::JsCast;
+    use web_sys::{self, Window};
+
+    use super::{changes, Sub};
+    use crate::cmd::Dispatcher;
+
+    enum Listener {
+        // The closure is only kept so that it is not freed while the interval is running
+        Interval {
+            handle: i32,
+            _closure: Closure<Fn()>,
+        },
+        Window(&'static str, Closure<Fn(web_sys::Event)>),
+    }
+
+    /// The listeners that are currently active for a program
+    pub struct Subscriptions<Msg> {
+        active: Vec<(Sub<Msg>, Listener)>,
+    }
+
+    impl<Msg> Subscriptions<Msg>
+    where
+        Msg: PartialEq + Clone + 'static,
+    {
+        pub fn new() -> Self {
+            Subscriptions { active: vec![] }
+        }
+
+        /// Remove the listeners that are gone from `subs` and add the ones that are new, see
+        /// `changes`. A listener that fails does not stop the others from
+        /// being updated, and the first error is returned. Listeners that could not be removed
+        /// stay active, so that their closures are not freed while they can still be called,
+        /// and removing them is tried again on the next update.
+        pub fn update(
+            &mut self,
+            subs: &Sub<Msg>,
+            dispatcher: &Dispatcher<Msg>,
+        ) -> Result<(), JsValue> {
+            let window = web_sys::window().expect("no global `window` exists");
+            let current = self
+                .active
+                .iter()
+                .map(|(sub, _)| sub.clone())
+                .collect::<Vec<_>>();
+            let changes = changes(&current, subs);
+            let mut result = Ok(());
+
+            let mut active = vec![];
+            for (index, (sub, listener)) in self.active.drain(..).enumerate() {
+                if !changes.remove.contains(&index) {
+                    active.push((sub, listener));
+                } else if let Err(err) = remove_listener(&window, &listener) {
+                    result = result.and(Err(err));
+                    active.push((sub, listener));
+                }
+            }
+            self.active = active;
+
+            for source in changes.add {
+                match add_listener(&window, &source, dispatcher) {
+                    Ok(listener) => self.active.push((source, listener)),
+                    Err(err) => result = result.and(Err(err)),
+                }
+            }
+
+            result
+        }
+    }
+
+    fn add_listener<Msg>(
+        window: &Window,
+        sub: &Sub<Msg>,
+        dispatcher: &Dispatcher<Msg>,
+    ) -> Result<Listener, JsValue>
+    where
+        Msg: Clone + 'static,
+    {
+        let dispatcher = dispatcher.clone();
+        let (type_, closure) = match sub {
+            Sub::Every { interval, msg } => {
+                let msg = msg.clone();
+                let closure = Closure::wrap(Box::new(move || {
+                    dispatcher.dispatch(msg.clone());
+                }) as Box<Fn()>);
+                let handle = window.set_interval_with_callback_and_timeout_and_arguments_0(
+                    closure.as_ref().unchecked_ref(),
+                    *interval,
+                )?;
+                return Ok(Listener::Interval {
+                    handle,
+                    _closure: closure,
+                });
+            }
+            Sub::KeyDown(to_message) => {
+                let to_message = *to_message;
+                let closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+                    if let Ok(event) = event.dyn_into::<web_sys::KeyboardEvent>() {
+                        dispatcher.dispatch(to_message(event.key()));
+                    }
+                }) as Box<Fn(_)>);
+                ("keydown", closure)
+            }
+            Sub::Resize(to_message) => {
+                let to_message = *to_message;
+                let closure = Closure::wrap(Box::new(move |_: web_sys::Event| {
+                    let window = web_sys::window().expect("no global `window` exists");
+                    let width = window.inner_width().ok().and_then(|w| w.as_f64());
+                    let height = window.inner_height().ok().and_then(|h| h.as_f64());
+                    dispatcher.dispatch(to_message(
+                        width.unwrap_or_default() as i32,
+                        height.unwrap_or_default() as i32,
+                    ));
+                }) as Box<Fn(_)>);
+                ("resize", closure)
+            }
+            Sub::PopState(to_message) => {
+                let to_message = *to_message;
+                let closure = Closure::wrap(Box::new(move |_: web_sys::Event| {
+                    let window = web_sys::window().expect("no global `window` exists");
+                    let href = window.location().href().unwrap_or_default();
+                    dispatcher.dispatch(to_message(href));
+                }) as Box<Fn(_)>);
+                ("popstate", closure)
+            }
+            Sub::None | Sub::Batch(_) => {
+                return Err(JsValue::from_str("None and Batch are not event sources"));
+            }
+        };
+
+        (window.as_ref() as &web_sys::EventTarget)
+            .add_event_listener_with_callback(type_, closure.as_ref().unchecked_ref())?;
+
+        Ok(Listener::Window(type_, closure))
+    }
+
+    fn remove_listener(window: &Window, listener: &Listener) -> Result<(), JsValue> {
+        match listener {
+            Listener::Interval { handle, .. } => window.clear_interval_with_handle(*handle),
+            Listener::Window(type_, closure) => {
+                (window.as_ref() as &web_sys::EventTarget)
+                    .remove_event_listener_with_callback(type_, closure.as_ref().unchecked_ref())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changes, Changes, Sub};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Tick,
+        Tock,
+        Key(String),
+        Resized(i32, i32),
+    }
+
+    fn key(key: String) -> Msg {
+        Msg::Key(key)
+    }
+
+    #[test]
+    fn sources_leave_out_none_and_batch() {
+        let subs = Sub::batch(vec![
+            Sub::none(),
+            Sub::every(100, Msg::Tick),
+            Sub::batch(vec![Sub::on_key_down(key), Sub::none()]),
+        ]);
+
+        assert_eq!(
+            subs.sources(),
+            vec![Sub::every(100, Msg::Tick), Sub::on_key_down(key)]
+        );
+        assert_eq!(Sub::<Msg>::none().sources(), vec![]);
+    }
+
+    #[test]
+    fn subscriptions_are_compared_by_what_they_listen_to() {
+        assert_eq!(Sub::every(100, Msg::Tick), Sub::every(100, Msg::Tick));
+        assert_ne!(Sub::every(100, Msg::Tick), Sub::every(200, Msg::Tick));
+        assert_ne!(Sub::every(100, Msg::Tick), Sub::every(100, Msg::Tock));
+        assert_eq!(Sub::on_key_down(key), Sub::on_key_down(key));
+        assert_ne!(Sub::on_key_down(key), Sub::on_pop_state(key));
+        assert_ne!(
+            Sub::on_resize(Msg::Resized),
+            Sub::on_resize(|width, _| Msg::Resized(width, width))
+        );
+    }
+
+    #[test]
+    fn changes_keep_the_sources_that_are_still_there() {
+        let active = vec![Sub::every(100, Msg::Tick), Sub::on_key_down(key)];
+        let subs = Sub::batch(vec![
+            Sub::on_key_down(key),
+            Sub::on_resize(Msg::Resized),
+            Sub::on_resize(Msg::Resized),
+        ]);
+
+        assert_eq!(
+            changes(&active, &subs),
+            Changes {
+                remove: vec![0],
+                add: vec![Sub::on_resize(Msg::Resized)],
+            }
+        );
+        assert_eq!(
+            changes(&active, &Sub::none()),
+            Changes {
+                remove: vec![0, 1],
+                add: vec![],
+            }
+        );
+        assert_eq!(
+            changes(&[], &Sub::every(100, Msg::Tock)),
+            Changes {
+                remove: vec![],
+                add: vec![Sub::every(100, Msg::Tock)],
+            }
+        );
+    }
+}
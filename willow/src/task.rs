@@ -0,0 +1,206 @@
+//! Asynchronous work for commands, see `Cmd::perform`.
+//!
+//! In the browser futures are run on the JavaScript event loop. Natively there is no event
+//! loop, so futures are queued until `run_until_stalled` is called. `testing::TestProgram` does
+//! that when it runs commands, so the same `update` can be tested without a browser.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::Cmd;
+
+/// A future that may fail, which can be chained like `Task` in Elm
+pub struct Task<T, E>(Pin<Box<Future<Output = Result<T, E>>>>);
+
+impl<T: 'static, E: 'static> Task<T, E> {
+    pub fn new<F>(future: F) -> Self
+    where
+        F: Future<Output = Result<T, E>> + 'static,
+    {
+        Task(Box::pin(future))
+    }
+
+    pub fn succeed(value: T) -> Self {
+        Task::new(async move { Ok(value) })
+    }
+
+    pub fn fail(err: E) -> Self {
+        Task::new(async move { Err(err) })
+    }
+
+    pub fn map<U, F>(self, f: F) -> Task<U, E>
+    where
+        U: 'static,
+        F: FnOnce(T) -> U + 'static,
+    {
+        Task::new(async move { self.await.map(f) })
+    }
+
+    pub fn map_err<E2, F>(self, f: F) -> Task<T, E2>
+    where
+        E2: 'static,
+        F: FnOnce(E) -> E2 + 'static,
+    {
+        Task::new(async move { self.await.map_err(f) })
+    }
+
+    /// Start the task from `f` when this one succeeds
+    pub fn and_then<U, F>(self, f: F) -> Task<U, E>
+    where
+        U: 'static,
+        F: FnOnce(T) -> Task<U, E> + 'static,
+    {
+        Task::new(async move {
+            match self.await {
+                Ok(value) => f(value).await,
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    /// A command that runs the task, and dispatches the message for the result
+    pub fn attempt<Msg, F>(self, to_message: F) -> Cmd<Msg>
+    where
+        Msg: Clone + 'static,
+        F: FnOnce(Result<T, E>) -> Msg + 'static,
+    {
+        Cmd::perform(self, to_message)
+    }
+}
+
+impl<T, E> Future for Task<T, E> {
+    type Output = Result<T, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// Run `future` in the background
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub fn spawn_local<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+pub use self::executor::{run_until_stalled, spawn_local};
+
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+mod executor {
+    use std::cell::RefCell;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Wake, Waker};
+
+    struct Spawned {
+        future: Pin<Box<Future<Output = ()>>>,
+        woken: Arc<Woken>,
+    }
+
+    struct Woken(AtomicBool);
+
+    impl Wake for Woken {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    thread_local! {
+        static SPAWNED: RefCell<Vec<Spawned>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Queue `future` to be run by `run_until_stalled`
+    pub fn spawn_local<F>(future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let spawned = Spawned {
+            future: Box::pin(future),
+            woken: Arc::new(Woken(AtomicBool::new(true))),
+        };
+        SPAWNED.with(|spawned_list| spawned_list.borrow_mut().push(spawned));
+    }
+
+    /// Poll the futures spawned on this thread until none of them can make progress. Returns
+    /// whether any of them are still waiting for something.
+    pub fn run_until_stalled() -> bool {
+        loop {
+            // Take the futures out, as polling them may spawn new ones
+            let (ready, waiting): (Vec<_>, Vec<_>) = SPAWNED
+                .with(|spawned| spawned.replace(vec![]))
+                .into_iter()
+                .partition(|spawned| spawned.woken.0.swap(false, Ordering::SeqCst));
+
+            let stalled = ready.is_empty();
+            let mut pending = waiting;
+            for mut spawned in ready {
+                let waker = Waker::from(spawned.woken.clone());
+                if spawned
+                    .future
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_pending()
+                {
+                    pending.push(spawned);
+                }
+            }
+
+            let has_pending = !pending.is_empty();
+            SPAWNED.with(|spawned| spawned.borrow_mut().extend(pending));
+            if stalled {
+                return has_pending;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{run_until_stalled, Task};
+    use crate::{Cmd, Dispatcher};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Msg {
+        Loaded(Result<i32, String>),
+    }
+
+    #[test]
+    fn performs_chained_tasks() {
+        let messages = Rc::new(RefCell::new(vec![]));
+        let sent = messages.clone();
+        let dispatcher = Dispatcher::new(move |message| sent.borrow_mut().push(message));
+
+        let cmd = Cmd::batch(vec![
+            Task::succeed(20)
+                .and_then(|value| Task::succeed(value + 1))
+                .map(|value| value * 2)
+                .attempt(Msg::Loaded),
+            Task::<i32, i32>::fail(404)
+                .and_then(|value| Task::succeed(value + 1))
+                .map_err(|status| format!("status {}", status))
+                .attempt(Msg::Loaded),
+        ]);
+        cmd.run(&dispatcher).unwrap();
+        assert!(messages.borrow().is_empty());
+
+        assert!(!run_until_stalled());
+        assert_eq!(
+            *messages.borrow(),
+            vec![
+                Msg::Loaded(Ok(42)),
+                Msg::Loaded(Err("status 404".to_owned()))
+            ]
+        );
+        // A future is only run once
+        assert!(cmd.run(&dispatcher).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+//! Run a program without a browser, to test the whole update and view loop. Events are
+//! simulated on the nodes of the current view, and go through the same `EventToMessage` as in
+//! the browser.
+//!
+//! Nodes are found with selectors, see `query`. The first node that matches is used.
+//!
+//! Commands returned by `update`, and the one from `Program::with_init_cmd`, are recorded
+//! instead of run, see `TestProgram::cmds` and `TestProgram::run_cmds`. Futures the commands
+//! start are run by `task::run_until_stalled`.
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+use crate::{
+    dom_event::{self, Event, EventData},
+    html::{Attribute, Html, HtmlTag},
+    task, Cmd, Dispatcher, Error, Program,
+};
+
+pub struct TestProgram<Model, Msg> {
+    view: Box<Fn(&Model) -> Html<Msg>>,
+    update: Box<Fn(&Msg, &mut Model) -> Cmd<Msg>>,
+    model: Model,
+    tree: Html<Msg>,
+    messages: Vec<Msg>,
+    cmds: Vec<Cmd<Msg>>,
+    // Messages sent by commands, waiting to be dispatched
+    queue: Rc<RefCell<Vec<Msg>>>,
+}
+
+impl<Model, Msg> TestProgram<Model, Msg>
+where
+    Model: Debug + Clone + 'static,
+    Msg: PartialEq + Debug + Clone + 'static,
+{
+    pub fn new(program: Program<Model, Msg>) -> Self {
+        let Program {
+            view,
+            update,
+            current_model,
+            init_cmd,
+            ..
+        } = program;
+        let model = current_model.into_inner();
+        let tree = view(&model);
+        let mut program = TestProgram {
+            view,
+            update,
+            model,
+            tree,
+            messages: vec![],
+            cmds: vec![],
+            queue: Rc::new(RefCell::new(vec![])),
+        };
+        program.record(init_cmd.into_inner());
+        program
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// The view of the current model
+    pub fn view(&self) -> &Html<Msg> {
+        &self.tree
+    }
+
+    /// Every message that has been dispatched, in order
+    pub fn messages(&self) -> &[Msg] {
+        &self.messages
+    }
+
+    /// The commands returned by `update` that have not been run yet. Batches are flattened,
+    /// and `Cmd::None` is left out.
+    pub fn cmds(&self) -> &[Cmd<Msg>] {
+        &self.cmds
+    }
+
+    /// Update the model with `message`, and render the view again
+    pub fn dispatch(&mut self, message: Msg) {
+        let cmd = (self.update)(&message, &mut self.model);
+        self.messages.push(message);
+        self.record(cmd);
+        self.tree = (self.view)(&self.model);
+    }
+
+    fn record(&mut self, cmd: Cmd<Msg>) {
+        match cmd {
+            Cmd::None => {}
+            Cmd::Batch(cmds) => {
+                for cmd in cmds {
+                    self.record(cmd);
+                }
+            }
+            cmd => self.cmds.push(cmd),
+        }
+    }
+
+    /// Run the recorded commands and the futures they start, and dispatch the messages they
+    /// send. Commands returned by those messages are recorded, and not run. A command that
+    /// fails does not stop the others, and the first error is returned after the messages are
+    /// dispatched.
+    pub fn run_cmds(&mut self) -> Result<(), Error> {
+        let queue = self.queue.clone();
+        let dispatcher = Dispatcher::new(move |message| queue.borrow_mut().push(message));
+
+        let mut result = Ok(());
+        for cmd in self.cmds.drain(..).collect::<Vec<_>>() {
+            result = result.and(cmd.run(&dispatcher));
+        }
+        task::run_until_stalled();
+
+        let messages = self.queue.replace(vec![]);
+        for message in messages {
+            self.dispatch(message);
+        }
+        result
+    }
+
+    /// The first node in the view that matches `selector`
+    pub fn find(&self, selector: &str) -> Option<&HtmlTag<Msg>> {
+        self.tree.find(selector)
+    }
+
+    /// Every node in the view that matches `selector`
+    pub fn find_all(&self, selector: &str) -> Vec<&HtmlTag<Msg>> {
+        self.tree.find_all(selector)
+    }
+
+    pub fn click(&mut self, selector: &str) -> Result<(), Error> {
+        self.fire(selector, event("click"))
+    }
+
+    /// Set the value of an input to `value`, like when the user types
+    pub fn input(&mut self, selector: &str, value: &str) -> Result<(), Error> {
+        self.fire(
+            selector,
+            EventData {
+                value: value.to_owned(),
+                ..event("input")
+            },
+        )
+    }
+
+    /// Press `key`, which is a key name like `"Enter"` or a single character
+    pub fn key_down(&mut self, selector: &str, key: &str) -> Result<(), Error> {
+        self.fire(
+            selector,
+            EventData {
+                key: key.to_owned(),
+                key_code: key_code(key),
+                ..event("keydown")
+            },
+        )
+    }
+
+    pub fn blur(&mut self, selector: &str) -> Result<(), Error> {
+        self.fire(selector, event("blur"))
+    }
+
+    /// Send `data` to the listeners for `data.type_` on the node that matches `selector`, and
+    /// dispatch the messages they give. Like in the browser, the event then bubbles up to the
+    /// listeners on the ancestors, unless it does not bubble or a listener stops propagation.
+    /// It is an error if no listener gets the event.
+    pub fn fire(&mut self, selector: &str, data: EventData) -> Result<(), Error> {
+        let messages = {
+            let path = self
+                .tree
+                .find_path(selector)
+                .ok_or_else(|| Error::from(format!("No node matches {:?}", selector)))?;
+            let bubbles = dom_event::bubbles(&data.type_);
+            let type_ = data.type_.clone();
+            let event = Event::from_data(data);
+
+            let mut listened = false;
+            let mut messages = vec![];
+            for (depth, node) in path.iter().enumerate().rev() {
+                if !bubbles && depth + 1 < path.len() {
+                    break;
+                }
+                let mut stopped = false;
+                for attr in &node.attrs {
+                    if let Attribute::Event {
+                        type_: listened_type,
+                        to_message,
+                        options,
+                        ..
+                    } = attr
+                    {
+                        if listened_type != &type_ {
+                            continue;
+                        }
+                        listened = true;
+                        stopped |= options.stop_propagation;
+                        messages.extend(to_message.to_message(&event));
+                    }
+                }
+                if stopped {
+                    break;
+                }
+            }
+            if !listened {
+                return Err(Error::from(format!(
+                    "Nothing listens to {} on {:?}",
+                    type_, selector
+                )));
+            }
+            messages
+        };
+
+        for message in messages {
+            self.dispatch(message);
+        }
+        Ok(())
+    }
+}
+
+fn event(type_: &str) -> EventData {
+    EventData {
+        type_: type_.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// The `keyCode` browsers give `key`
+fn key_code(key: &str) -> u32 {
+    match key {
+        "Backspace" => 8,
+        "Tab" => 9,
+        "Enter" => 13,
+        "Escape" => 27,
+        " " => 32,
+        "ArrowLeft" => 37,
+        "ArrowUp" => 38,
+        "ArrowRight" => 39,
+        "ArrowDown" => 40,
+        "Delete" => 46,
+        key => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c.to_ascii_uppercase() as u32,
+                _ => 0,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestProgram;
+    use crate::attributes::{class, key, value};
+    use crate::cmd::Effect;
+    use crate::events::{on_blur, on_click, on_enter, on_input};
+    use crate::html::{button, div, input, li, text, ul, Html};
+    use crate::{Cmd, Dispatcher, Error, Program, Sub};
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Msg {
+        UpdateField(String),
+        Add,
+        Delete(usize),
+        Batch,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Fail;
+
+    impl Effect<Msg> for Fail {
+        fn run(&self, _: &Dispatcher<Msg>) -> Result<(), Error> {
+            Err(Error::from("failed"))
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Model {
+        field: String,
+        entries: Vec<String>,
+    }
+
+    fn update(msg: &Msg, model: &mut Model) -> Cmd<Msg> {
+        match msg {
+            Msg::UpdateField(field) => model.field = field.clone(),
+            Msg::Add => {
+                model.entries.push(model.field.clone());
+                model.field.clear();
+                return Cmd::Message(Msg::UpdateField("added".to_owned()));
+            }
+            Msg::Delete(index) => {
+                model.entries.remove(*index);
+            }
+            Msg::Batch => {
+                return Cmd::batch(vec![
+                    Cmd::none(),
+                    Cmd::Message(Msg::Delete(0)),
+                    Cmd::batch(vec![Cmd::Message(Msg::UpdateField("b".to_owned()))]),
+                ]);
+            }
+        }
+        Cmd::none()
+    }
+
+    fn view(model: &Model) -> Html<Msg> {
+        div(
+            &[],
+            &[
+                input(
+                    &[
+                        class("new-todo"),
+                        value(&model.field),
+                        on_input(Msg::UpdateField),
+                        on_enter(Msg::Add),
+                    ],
+                    &[],
+                ),
+                ul(
+                    &[class("todo-list")],
+                    &model
+                        .entries
+                        .iter()
+                        .enumerate()
+                        .map(|(index, entry)| {
+                            li(
+                                &[key(index.to_string())],
+                                &[
+                                    text(entry),
+                                    button(&[class("destroy"), on_click(Msg::Delete(index))], &[]),
+                                ],
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            ],
+        )
+    }
+
+    fn program() -> TestProgram<Model, Msg> {
+        TestProgram::new(Program::new(
+            view,
+            update,
+            |_| Sub::none(),
+            Model::default(),
+        ))
+    }
+
+    #[test]
+    fn simulates_events() {
+        let mut app = program();
+
+        app.input(".new-todo", "Buy milk").unwrap();
+        app.key_down(".new-todo", "a").unwrap();
+        app.key_down(".new-todo", "Enter").unwrap();
+
+        assert_eq!(app.find_all(".todo-list > li").len(), 1);
+        assert_eq!(app.model().entries, vec!["Buy milk".to_owned()]);
+        assert_eq!(
+            app.messages(),
+            &[Msg::UpdateField("Buy milk".to_owned()), Msg::Add]
+        );
+
+        app.click("li[key=0] .destroy").unwrap();
+        assert!(app.find("li").is_none());
+        assert!(app.blur(".new-todo").is_err());
+    }
+
+    #[test]
+    fn events_bubble_until_propagation_is_stopped() {
+        let view = |_: &Model| {
+            div(
+                &[class("outer"), on_click(Msg::Delete(0)), on_blur(Msg::Add)],
+                &[
+                    button(&[class("plain")], &[]),
+                    button(&[class("stop"), on_click(Msg::Add).stop_propagation()], &[]),
+                ],
+            )
+        };
+        let mut app = TestProgram::new(Program::new(
+            view,
+            |_, _| Cmd::none(),
+            |_| Sub::none(),
+            Model::default(),
+        ));
+
+        app.click(".plain").unwrap();
+        app.click(".stop").unwrap();
+        assert_eq!(app.messages(), &[Msg::Delete(0), Msg::Add]);
+        // Blur does not bubble
+        assert!(app.blur(".plain").is_err());
+    }
+
+    #[test]
+    fn records_and_runs_cmds() {
+        let mut app = program();
+
+        app.dispatch(Msg::Add);
+        app.dispatch(Msg::Delete(0));
+        assert_eq!(
+            app.cmds(),
+            &[Cmd::Message(Msg::UpdateField("added".to_owned()))]
+        );
+
+        app.run_cmds().unwrap();
+        assert_eq!(app.model().field, "added");
+        assert!(app.cmds().is_empty());
+
+        app.dispatch(Msg::Batch);
+        assert_eq!(
+            app.cmds(),
+            &[
+                Cmd::Message(Msg::Delete(0)),
+                Cmd::Message(Msg::UpdateField("b".to_owned()))
+            ]
+        );
+    }
+    #[test]
+    fn runs_every_cmd_when_one_fails() {
+        let mut app = TestProgram::new(
+            Program::new(view, update, |_| Sub::none(), Model::default()).with_init_cmd(
+                Cmd::batch(vec![
+                    Cmd::effect(Fail),
+                    Cmd::Message(Msg::UpdateField("a".to_owned())),
+                    Cmd::effect(Fail),
+                ]),
+            ),
+        );
+
+        assert_eq!(app.run_cmds(), Err(Error::from("failed")));
+        assert_eq!(app.model().field, "a");
+        assert!(app.cmds().is_empty());
+    }
+}
@@ -1,11 +1,39 @@
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
 use wasm_bindgen::prelude::*;
 
+// Natively there is no console, which also lets tests run with the `web` feature
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     pub fn log(a: &str);
 }
 
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+pub fn log(a: &str) {
+    println!("{}", a);
+}
+
+/// Milliseconds from an arbitrary point in time, for measuring how long things take
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub fn now() -> f64 {
+    let window = web_sys::window().expect("no global `window` exists");
+    let performance = window
+        .performance()
+        .expect("should have performance on window");
+    performance.now()
+}
+
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+pub fn now() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_secs() as f64 * 1000.0 + f64::from(since_epoch.subsec_nanos()) / 1_000_000.0
+}
+
 #[macro_export]
 macro_rules! console_log {
     ($($t:tt)*) => ($crate::utils::log(&format_args!($($t)*).to_string()))